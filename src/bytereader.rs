@@ -52,7 +52,7 @@ impl<'a> ByteReader<'a> {
     pub fn try_accept(&mut self, expected: &[u8]) -> bool {
         let current_offset = self.offset;
 
-        if let Ok(_) = self.accept(expected) {
+        if self.accept(expected).is_ok() {
             true
         }
         else {
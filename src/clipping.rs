@@ -0,0 +1,177 @@
+use crate::shader::{Varying, VertexOutput};
+use crate::vector;
+
+// The view frustum in clip space is the region where -w <= x <= w, -w <= y <= w and
+// -w <= z <= w. Each of the six inequalities is one plane.
+// For each plane we have a function telling us how far inside of it a point is. Positive means
+// inside, negative means outside and zero means right on the plane.
+// We clip before the perspective divide because that's where this is simple: everything is still
+// linear, and points behind the camera (w < 0) haven't been flipped around yet.
+const PLANES: [fn(&vector::Vector4) -> f64; 6] = [
+    |p| p.w + p.x, // Left
+    |p| p.w - p.x, // Right
+    |p| p.w + p.y, // Bottom
+    |p| p.w - p.y, // Top
+    |p| p.w + p.z, // Near
+    |p| p.w - p.z, // Far
+];
+
+// Linear interpolation between two vertices.
+// Clip space is before the perspective divide, so a plain lerp of the varyings is correct here.
+fn lerp<V: Varying>(a: &VertexOutput<V>, b: &VertexOutput<V>, t: f64) -> VertexOutput<V> {
+    VertexOutput {
+        position: a.position.lerp(&b.position, t),
+        varyings: a.varyings.scale(1.0 - t).add(&b.varyings.scale(t)),
+    }
+}
+
+/// Clip a triangle against the view frustum using the Sutherland-Hodgman algorithm.
+///
+/// The result is a convex polygon with anywhere between 0 (the triangle is completely outside)
+/// and 9 vertices, in the same winding order as the triangle. It can be drawn as a fan of
+/// triangles around the first vertex.
+pub fn clip_triangle<V: Varying>(triangle: &[VertexOutput<V>; 3]) -> Vec<VertexOutput<V>> {
+    // Most triangles are either completely inside or completely outside. No need to build a new
+    // polygon for those.
+    let inside_all = |v: &VertexOutput<V>| PLANES.iter().all(|plane| plane(&v.position) >= 0.0);
+
+    if triangle.iter().all(inside_all) {
+        return triangle.to_vec();
+    }
+
+    for plane in PLANES.iter() {
+        if triangle.iter().all(|v| plane(&v.position) < 0.0) {
+            return Vec::new();
+        }
+    }
+
+    let mut polygon = triangle.to_vec();
+
+    // Walk around the polygon once for each plane, keeping the parts on the inside.
+    // For every edge going from `previous` to `current`:
+    // - Both inside: keep `current`
+    // - Going out: keep the point where the edge crosses the plane
+    // - Coming in: keep the crossing point, then `current`
+    // - Both outside: keep nothing
+    for plane in PLANES.iter() {
+        if polygon.is_empty() {
+            break;
+        }
+
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        let mut previous = polygon[polygon.len() - 1];
+        let mut previous_distance = plane(&previous.position);
+
+        for current in polygon.iter() {
+            let current_distance = plane(&current.position);
+
+            if (previous_distance >= 0.0) != (current_distance >= 0.0) {
+                let t = previous_distance / (previous_distance - current_distance);
+                clipped.push(lerp(&previous, current, t));
+            }
+
+            if current_distance >= 0.0 {
+                clipped.push(*current);
+            }
+
+            previous = *current;
+            previous_distance = current_distance;
+        }
+
+        polygon = clipped;
+    }
+
+    // Anything with less than 3 vertices doesn't cover any area
+    if polygon.len() < 3 {
+        polygon.clear();
+    }
+
+    polygon
+}
+
+/// Clip a line segment against the view frustum.
+/// Returns None if no part of the line is visible.
+pub fn clip_line(a: &vector::Vector4, b: &vector::Vector4) -> Option<(vector::Vector4, vector::Vector4)> {
+    // How far along the line (from a to b) the visible part starts and ends
+    let mut t_start: f64 = 0.0;
+    let mut t_end: f64 = 1.0;
+
+    for plane in PLANES.iter() {
+        let (distance_a, distance_b) = (plane(a), plane(b));
+
+        if distance_a < 0.0 && distance_b < 0.0 {
+            return None;
+        }
+
+        if distance_a < 0.0 {
+            // Going in
+            t_start = t_start.max(distance_a / (distance_a - distance_b));
+        }
+        else if distance_b < 0.0 {
+            // Going out
+            t_end = t_end.min(distance_a / (distance_a - distance_b));
+        }
+    }
+
+    if t_start > t_end {
+        return None;
+    }
+
+    Some((a.lerp(b, t_start), a.lerp(b, t_end)))
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::clipping::{clip_line, clip_triangle};
+    use crate::shader::VertexOutput;
+    use crate::vector::Vector4;
+
+    fn vertex(x: f64, y: f64, z: f64) -> VertexOutput<f64> {
+        VertexOutput { position: Vector4::new(x, y, z, 1.0), varyings: x }
+    }
+
+    #[test]
+    fn test_triangle_inside_is_untouched() {
+        let polygon = clip_triangle(&[vertex(0.0, 0.0, 0.0), vertex(0.5, 0.0, 0.0), vertex(0.0, 0.5, 0.0)]);
+
+        assert_eq!(polygon.len(), 3);
+    }
+
+    #[test]
+    fn test_triangle_outside_is_dropped() {
+        let polygon = clip_triangle(&[vertex(2.0, 0.0, 0.0), vertex(3.0, 0.0, 0.0), vertex(2.0, 0.5, 0.0)]);
+
+        assert!(polygon.is_empty());
+    }
+
+    #[test]
+    fn test_triangle_crossing_a_plane() {
+        // One corner sticks out of the right side. Cutting it off leaves a quad.
+        let polygon = clip_triangle(&[vertex(0.0, 0.0, 0.0), vertex(3.0, 0.0, 0.0), vertex(0.0, 0.5, 0.0)]);
+
+        assert_eq!(polygon.len(), 4);
+
+        for v in polygon.iter() {
+            assert!(v.position.x <= 1.0 + 1e-9);
+            // The varying follows the position
+            assert!((v.varyings - v.position.x).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_triangle_behind_the_camera_is_dropped() {
+        let behind = |x: f64, y: f64| VertexOutput { position: Vector4::new(x, y, 0.5, -1.0), varyings: () };
+
+        assert!(clip_triangle(&[behind(0.0, 0.0), behind(0.5, 0.0), behind(0.0, 0.5)]).is_empty());
+    }
+
+    #[test]
+    fn test_clip_line() {
+        let (a, b) = clip_line(&Vector4::new(-2.0, 0.0, 0.0, 1.0), &Vector4::new(2.0, 0.0, 0.0, 1.0)).unwrap();
+
+        assert!((a.x + 1.0).abs() < 1e-9);
+        assert!((b.x - 1.0).abs() < 1e-9);
+
+        assert!(clip_line(&Vector4::new(-2.0, 2.0, 0.0, 1.0), &Vector4::new(2.0, 2.0, 0.0, 1.0)).is_none());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::vector;
+
 // 32-bit color
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Color32 { r: u8, g: u8, b: u8, a: u8 }
 
 // 24-bit color
@@ -25,6 +27,32 @@ impl Color32 {
         Color32 { r: bytes[0], g: bytes[1] , b: bytes[2], a: bytes[3] }
     }
 
+    // Multiply the color channels by `factor`, leaving the alpha channel alone.
+    // Values are clamped to the [0, 255] range.
+    pub fn scaled(&self, factor: f64) -> Color32 {
+        let scale = |channel: u8| (channel as f64 * factor).clamp(0.0, 255.0) as u8;
+
+        Color32 { r: scale(self.r), g: scale(self.g), b: scale(self.b), a: self.a }
+    }
+
+    // Convert a floating point color (r, g, b, a in [0, 1]) to 8 bits per channel.
+    // Values outside of [0, 1] are clamped.
+    pub fn from_vector(color: &vector::Vector4) -> Color32 {
+        let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        Color32 { r: channel(color.x), g: channel(color.y), b: channel(color.z), a: channel(color.w) }
+    }
+
+    // Convert to a floating point color with channels in [0, 1]
+    pub fn to_vector(&self) -> vector::Vector4 {
+        vector::Vector4 {
+            x: self.r as f64 / 255.0,
+            y: self.g as f64 / 255.0,
+            z: self.b as f64 / 255.0,
+            w: self.a as f64 / 255.0
+        }
+    }
+
     // Convert a Color24 struct into Color32 by adding an alpha channel.
     pub fn from_color24(color: &Color24) -> Color32 {
         Color32 {
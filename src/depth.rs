@@ -0,0 +1,177 @@
+use crate::color;
+use crate::tga;
+
+/// How a fragment's depth is compared against what is already in the depth buffer.
+/// The fragment passes if `fragment_depth <op> stored_depth` holds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DepthFunc {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Always,
+    Never,
+}
+
+impl DepthFunc {
+    pub fn passes(&self, fragment_depth: f64, stored_depth: f64) -> bool {
+        match self {
+            DepthFunc::Less => fragment_depth < stored_depth,
+            DepthFunc::LessEqual => fragment_depth <= stored_depth,
+            DepthFunc::Greater => fragment_depth > stored_depth,
+            DepthFunc::GreaterEqual => fragment_depth >= stored_depth,
+            DepthFunc::Always => true,
+            DepthFunc::Never => false,
+        }
+    }
+}
+
+/// Per-pixel depth values along with the state that decides how they get tested and updated.
+///
+/// Depth values produced by the rasterizer go from 0 (near plane) to 1 (far plane). The default
+/// setup is the usual one: clear to 1.0 and keep whatever is closest (`DepthFunc::Less`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthBuffer {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+    /// The value every pixel gets reset to by `clear`
+    pub clear_value: f64,
+    /// The comparison a fragment has to pass to be drawn
+    pub compare: DepthFunc,
+    /// When false, fragments are still tested but the buffer is left untouched
+    pub write_enabled: bool,
+}
+
+impl DepthBuffer {
+    pub fn new(width: usize, height: usize) -> DepthBuffer {
+        let clear_value = 1.0;
+
+        DepthBuffer {
+            width,
+            height,
+            values: vec![clear_value; width * height],
+            clear_value,
+            compare: DepthFunc::Less,
+            write_enabled: true,
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Reset every pixel to `clear_value`
+    pub fn clear(&mut self) {
+        let clear_value = self.clear_value;
+
+        self.values.iter_mut().for_each(|value| *value = clear_value);
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f64 {
+        self.values[y * self.width + x]
+    }
+
+    /// Check whether a fragment at (x, y) with the given depth should be drawn
+    pub fn test(&self, x: usize, y: usize, depth: f64) -> bool {
+        self.compare.passes(depth, self.get(x, y))
+    }
+
+    /// Store the depth of a fragment that was drawn. Does nothing if writes are disabled.
+    pub fn write(&mut self, x: usize, y: usize, depth: f64) {
+        if self.write_enabled {
+            self.values[y * self.width + x] = depth;
+        }
+    }
+
+    /// Turn the depth buffer into a grayscale image to look at.
+    /// The depth values that were actually written get stretched over the whole gray range,
+    /// with the closest being white and the farthest being dark gray. Pixels that still hold the
+    /// clear value are black.
+    pub fn to_tga(&self) -> tga::TGAFile {
+        let mut image = tga::TGAFile::new(self.width as u16, self.height as u16);
+
+        let written = self.values.iter().cloned().filter(|value| *value != self.clear_value);
+        let (min, max) = written.fold((f64::MAX, f64::MIN), |(min, max), value| (min.min(value), max.max(value)));
+        let range = if max > min { max - min } else { 1.0 };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let value = self.get(x, y);
+
+                if value == self.clear_value {
+                    continue;
+                }
+
+                let gray = (255.0 - (value - min) / range * 200.0) as u8;
+
+                image.set(x as u16, y as u16, &color::Color32::new(gray, gray, gray, 255)).unwrap();
+            }
+        }
+
+        image
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::depth::{DepthBuffer, DepthFunc};
+
+    #[test]
+    fn test_compare_functions() {
+        let mut buffer = DepthBuffer::new(1, 1);
+        buffer.write(0, 0, 0.5);
+
+        buffer.compare = DepthFunc::Less;
+        assert!(buffer.test(0, 0, 0.4) && !buffer.test(0, 0, 0.5));
+
+        buffer.compare = DepthFunc::LessEqual;
+        assert!(buffer.test(0, 0, 0.5) && !buffer.test(0, 0, 0.6));
+
+        buffer.compare = DepthFunc::Greater;
+        assert!(buffer.test(0, 0, 0.6) && !buffer.test(0, 0, 0.5));
+
+        buffer.compare = DepthFunc::Always;
+        assert!(buffer.test(0, 0, 2.0));
+
+        buffer.compare = DepthFunc::Never;
+        assert!(!buffer.test(0, 0, -2.0));
+    }
+
+    #[test]
+    fn test_write_mask_and_clear() {
+        let mut buffer = DepthBuffer::new(2, 1);
+        buffer.write_enabled = false;
+        buffer.write(0, 0, 0.25);
+        assert_eq!(buffer.get(0, 0), 1.0);
+
+        buffer.write_enabled = true;
+        buffer.write(0, 0, 0.25);
+        assert_eq!(buffer.get(0, 0), 0.25);
+
+        buffer.clear_value = 0.0;
+        buffer.clear();
+        assert_eq!(buffer.get(0, 0), 0.0);
+        assert_eq!(buffer.get(1, 0), 0.0);
+    }
+
+    #[test]
+    fn test_to_tga() {
+        let mut buffer = DepthBuffer::new(3, 1);
+        buffer.write(0, 0, 0.2);
+        buffer.write(1, 0, 0.6);
+
+        let image = buffer.to_tga();
+        // Just the color, the alpha of unwritten pixels doesn't matter
+        let rgb = |x| image.get(x, 0).get_pixel_value() >> 8;
+
+        assert_eq!(rgb(0), 0xffffff);
+        assert_eq!(rgb(1), 0x373737);
+        // Still at the far plane
+        assert_eq!(rgb(2), 0);
+    }
+}
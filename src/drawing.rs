@@ -1,17 +1,81 @@
-use crate::wavefront;
-use crate::tga;
 use crate::point;
-use crate::color;
+use crate::clipping;
+use crate::framebuffer;
+use crate::image;
+use crate::matrix;
+use crate::vector;
+use crate::shader::{Fragment, FragmentInput, FragmentShader, Varying, VertexOutput, VertexShader};
 
+/// Which way round the corners of a triangle go, as seen on the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrontFace {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Which triangles get thrown away before they're rasterized
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+/// The state that decides whether a triangle gets drawn at all, before any pixels are looked at.
+///
+/// The defaults are OpenGL's: counter-clockwise triangles face the camera, and nothing gets
+/// culled. Culling back faces skips about half the triangles of a closed mesh, the ones on the
+/// far side that would end up hidden anyway.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RasterizerState {
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+}
+
+impl Default for RasterizerState {
+    fn default() -> RasterizerState {
+        RasterizerState { cull_mode: CullMode::None, front_face: FrontFace::CounterClockwise }
+    }
+}
+
+impl RasterizerState {
+    /// Whether a triangle whose corners go around `signed_area` (positive for counter-clockwise,
+    /// in window coordinates) is facing the camera
+    pub fn is_front_facing(&self, signed_area: f64) -> bool {
+        match self.front_face {
+            FrontFace::CounterClockwise => signed_area > 0.0,
+            FrontFace::Clockwise => signed_area < 0.0,
+        }
+    }
 
-pub fn line(
-    x0: u16,
-    y0: u16,
-    x1: u16,
-    y1: u16, 
-    img: &mut tga::TGAFile,
-    color: &color::Color32) 
+    /// Whether a triangle with the given signed area gets thrown away
+    pub fn culls(&self, signed_area: f64) -> bool {
+        match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Front => self.is_front_facing(signed_area),
+            CullMode::Back => !self.is_front_facing(signed_area),
+        }
+    }
+}
+
+pub fn line<P: Copy>(
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize, 
+    img: &mut image::Image<P>,
+    color: &P) 
 {
+    // Nothing to draw on
+    if img.get_width() == 0 || img.get_height() == 0 {
+        return;
+    }
+
+    // Pixels that fall outside of the image just get skipped
+    let mut plot = |x: usize, y: usize| {
+        let _ = img.set(x, y, *color);
+    };
+
     // Start by getting the first pixel to plot
     let mut x = x0;
     let mut y = y0; 
@@ -38,7 +102,7 @@ pub fn line(
     if dx.abs() >= dy.abs() {
         while x != x1 {
             // Set the pixel.
-            img.set(x as u16, y as u16, &color).unwrap();
+            plot(x, y);
             // Set x to the next pixel
             if x0 < x1 { x += 1; } else { x -= 1; }
             // Increment the error by the slope
@@ -54,11 +118,11 @@ pub fn line(
         }
         
         // See explanation below.
-        img.set(x1, y1, &color).unwrap();
+        plot(x1, y1);
     }
     else {
         while y != y1 {
-            img.set(x as u16, y as u16, &color).unwrap();
+            plot(x, y);
             
             if y0 < y1 { y += 1; } else { y -= 1; }
 
@@ -76,182 +140,902 @@ pub fn line(
         // Reason: Consider the situation where y0 is 0. If we looped until y <= y0. y would be 0,
         // yet we would stilll try to loop and eventually hit an error when we try to decrement
         // from an unsinged integer.
-        img.set(x1, y1, &color).unwrap();
+        plot(x1, y1);
     }
 }
 
-pub fn line_from_vertices(
+pub fn line_from_vertices<P: Copy>(
     v0: point::Point3D,
     v1: point::Point3D,
-    image: &mut tga::TGAFile,
-    color: &color::Color32)
+    image: &mut image::Image<P>,
+    color: &P)
 {
-    line(v0.x as u16, v0.y as u16, v1.x as u16, v1.y as u16, image, color);
+    if image.get_width() == 0 || image.get_height() == 0 {
+        return;
+    }
+
+    // A point sitting right on the right or top edge of the screen would land one pixel past the
+    // end of the image, so we keep everything inside.
+    let max_x = (image.get_width() - 1) as f64;
+    let max_y = (image.get_height() - 1) as f64;
+
+    line(
+        v0.x.clamp(0.0, max_x) as usize,
+        v0.y.clamp(0.0, max_y) as usize,
+        v1.x.clamp(0.0, max_x) as usize,
+        v1.y.clamp(0.0, max_y) as usize,
+        image,
+        color);
 }
 
-pub fn triangle(
-    v0: point::Point3D,
-    v1: point::Point3D, 
-    v2: point::Point3D, 
-    t0: point::Point2D,
-    t1: point::Point2D,
-    t2: point::Point2D,
-    image: &mut tga::TGAFile, 
-    zbuffer: &mut Vec<f64>, 
-    texture: &mut tga::TGAFile,
-    color: &color::Color32) 
+/// Run the vertex shader on every corner of every triangle and rasterize the results
+pub fn draw_triangles<VS, FS, I, C, O>(
+    vertex_shader: &VS,
+    fragment_shader: &FS,
+    triangles: I,
+    rasterizer: &RasterizerState,
+    target: &mut framebuffer::Framebuffer<C>)
+where
+    VS: VertexShader,
+    FS: FragmentShader<O, Varyings = VS::Varyings>,
+    I: IntoIterator<Item = [VS::Input; 3]>,
+    C: framebuffer::ColorTarget<O>
 {
-    
+    for [a, b, c] in triangles {
+        let vertices = [
+            vertex_shader.vertex(&a),
+            vertex_shader.vertex(&b),
+            vertex_shader.vertex(&c)];
 
-    // Then figure out how to color it in :p
-    // Step 1: Figure out the "bounding box" of the triangle.
-    // I am lazy and don't feel like doing this in a scholarly 
-    // way, so here we are.
-    let mut min_y = std::f64::MAX; 
-    let mut max_y = std::f64::MIN; 
-    let mut min_x = std::f64::MAX; 
-    let mut max_x = std::f64::MIN; 
-    
-    // Yes, I know this is horrid to look at
-    // but bear with me.
-    if v0.y < min_y { min_y = v0.y; }
-    if v1.y < min_y { min_y = v1.y; }
-    if v2.y < min_y { min_y = v2.y; }
-    
-    if v0.x < min_x { min_x = v0.x; }
-    if v1.x < min_x { min_x = v1.x; }
-    if v2.x < min_x { min_x = v2.x; }
+        triangle(&vertices, fragment_shader, rasterizer, target);
+    }
+}
 
-    if v0.y > max_y { max_y = v0.y; }
-    if v1.y > max_y { max_y = v1.y; }
-    if v2.y > max_y { max_y = v2.y; }
-    
-    // Almost there...
-    if v0.x > max_x { max_x = v0.x; }
-    if v1.x > max_x { max_x = v1.x; }
-    if v2.x > max_x { max_x = v2.x; }
-
-    // Flag any degenerate triangles...
-    if (min_x - max_x).abs() < 1.0 || (min_y - max_y).abs() < 1.0 {
-                    line_from_vertices(v0, v1, image, &color::Color32::new(255, 0, 0, 255));
-                    line_from_vertices(v1, v2, image, &color::Color32::new(255, 0, 0, 255));
-                    line_from_vertices(v2, v0, image, &color::Color32::new(255, 0, 0, 255));
-                    return;
+/// Draw triangles out of a vertex buffer and an index buffer, three indices per triangle (see
+/// `mesh::Mesh`).
+/// The vertex shader runs once per vertex, however many triangles share it.
+pub fn draw_indexed<VS, FS, C, O>(
+    vertex_shader: &VS,
+    fragment_shader: &FS,
+    vertices: &[VS::Input],
+    indices: &[usize],
+    rasterizer: &RasterizerState,
+    target: &mut framebuffer::Framebuffer<C>)
+where
+    VS: VertexShader,
+    FS: FragmentShader<O, Varyings = VS::Varyings>,
+    C: framebuffer::ColorTarget<O>
+{
+    let outputs: Vec<VertexOutput<VS::Varyings>> = vertices.iter().map(|v| vertex_shader.vertex(v)).collect();
+
+    for corners in indices.chunks_exact(3) {
+        triangle(&[outputs[corners[0]], outputs[corners[1]], outputs[corners[2]]], fragment_shader, rasterizer, target);
     }
-    
+}
 
-    // Ok sweet, I have found the bounding box.
-    // Step 2: Determine if a given point P is inside the triangle.
-    // This is my take in barycentric coordinates since I don't 
-    // don't get barycentric coordinates 100%
-    //
-    // Given a triangle ABC, any point P, can be reached via a vector
-    // that is a linear combination of AB and AC.
-    // Meaning for any point P, we will have AP = uAB + vAC.
-    // where u and v are some constants.
-    // This is nice and all, but how does it help me?
-    // Well turns out that if the constants u and v are positive and 
-    // their sum is less than 1, then the point P will be in the triangle
-    // "enclosed" by vectors AB and AC. Draw it out to see why this is at least
-    // intuitive. I don't claim to have proved this, it just sounds right
-    //
-    // In sum, to figure out if a point P is in the triangle ABC, I need to find
-    // the constants u and v. If they are positive and sum up to less than 1, the point
-    // is inside the triangle.
-    // 
-    // To find the constants u and v, I think in the following manner.
-    // I consider the vectors AB and AC to be transformed versions of the basis vectors
-    // i and j (the 1-unit vectors in the x and y direction respectively).
-    // The matrix that defines this transformation is:
-    // |AB_x AB_x| "Let's call this matrix A"
-    // |AB_y AC_y|
-    // 
-    // If that doesn't make sense, take a look at the first 3~4 episodes of 3blue1brown's
-    // "Essense of Linear Algebra" playlist. It shed a whole new light on linear algebra
-    // for me.
-    //
-    // So i could ask myself the question:
-    // What is the vector t that when that transformation is applied, gives me the vector AP?
-    // (Deciding what question to ask is often the hardest thing. It took me one week of 
-    // looking at triangles and drawing vectors to come up with this. This is partly due to the
-    // fact that I'm practically a newbie at linear algebra)
-    // 
-    // The equation that asks that question is: Matrix A * t = Vector AP.
-    // To find vector t, we can find the inverse of the Matrix A, Matrix A^-1 and "multiply"
-    // it by Vector AP, which is exactly what I am doing below.
-    // Now, since the linear transformation that Matrix A defines takes the i and j vectors and 
-    // transforms them into AB and AC, that same linear transformation takes the t vector and 
-    // transforms it to AP. Well, we just found t. What comes next is another totally unproven
-    // assumption that i made. It just seemed right.
-    // If t_x and t_y are greater than 0 and their sum is less than 1, then the vector t is within
-    // the triangle formed by the i and j vectors.
-    // Additionally, If the point at the tip of vector t is within the triangle formed by the i and j vectors,
-    // THEN the transformed version of t, AP, must be also within the triangle formed by the
-    // transformed versions of the i and j vectors, AB and AC.
-    // 
-    // BOOM. DONE. EZE OUT.
-    let ab = v1 - v0;
-    let ac = v2 - v0;
-
-    let inverse_determinant = 1.0 / (ab.x * ac.y - ac.x * ab.y);
-
-    let matrix_inverse = [(ac.y * inverse_determinant, ab.y * -1.0 * inverse_determinant),
-                            (ac.x * -1.0 * inverse_determinant, ab.x * inverse_determinant)];
-
-    let mut p_x = min_x;
-     
-    while p_x <= max_x + 1.0 {
-        let mut p_y = min_y;
-
-        while p_y <= max_y + 1.0 {
-            let (ap_x, ap_y) = (p_x - v0.x, p_y - v0.y);
-
-            let u = ap_x * matrix_inverse[0].0 + ap_y * matrix_inverse[1].0;
-
-            let v = ap_x * matrix_inverse[0].1 + ap_y * matrix_inverse[1].1;
-
-            let w = 1.0 - (u + v);
-
-            let interpolated_z_value = u * v0.z + v * v1.z + w * v2.z;
-            
-            let index = p_y as usize * image.get_width() as usize + p_x as usize;           
+/// Draw a single triangle given in clip space.
+/// The triangle is clipped against the view frustum first, so only the visible part of it gets
+/// rasterized.
+/// Then it gets culled if it faces the wrong way (see `RasterizerState`).
+/// Fragments go through the framebuffer's depth and stencil tests, and only those that pass get
+/// shaded.
+pub fn triangle<S, C, O>(
+    vertices: &[VertexOutput<S::Varyings>; 3],
+    shader: &S,
+    rasterizer: &RasterizerState,
+    target: &mut framebuffer::Framebuffer<C>)
+where
+    S: FragmentShader<O>,
+    C: framebuffer::ColorTarget<O>
+{
+    let polygon = clipping::clip_triangle(vertices);
+
+    if polygon.len() < 3 {
+        return;
+    }
 
-            let texture_width = texture.get_width() as f64;
+    // Which way the triangle faces comes from the winding of its corners on the screen. We look
+    // at the clipped polygon rather than the triangle: with a corner behind the camera, the
+    // perspective divide would flip that corner around and the winding with it.
+    let viewport = matrix::Matrix4::viewport(0.0, 0.0, target.get_width() as f64, target.get_height() as f64);
+    let corners: Vec<point::Point3D> = polygon.iter().map(|vertex| (viewport * vertex.position).to_point()).collect();
 
-            let texture_height = texture.get_height() as f64;
-            
-            let texture_v0_x = t0.x * texture_width;
-            let texture_v0_y = t0.y * texture_height;
-            let texture_v0 = texture.get(texture_v0_x as u16, texture_v0_y as u16);
+    if rasterizer.culls(signed_area(&corners)) {
+        return;
+    }
 
-            let texture_v1_x = t1.x * texture_width;
-            let texture_v1_y = t1.y * texture_height;
-            let texture_v1 = texture.get(texture_v1_x as u16, texture_v1_y as u16);
-            
-            let texture_v2_x = t2.x * texture_width;
-            let texture_v2_y = t2.y * texture_height;
-            let texture_v2 = texture.get(texture_v2_x as u16, texture_v2_y as u16);
+    // The clipped polygon is convex, so a fan around the first vertex covers it
+    for i in 1..polygon.len().saturating_sub(1) {
+        rasterize(&[polygon[0], polygon[i], polygon[i + 1]], shader, target);
+    }
+}
+
+/// Draw a line given in clip space. The line gets clipped against the view frustum first.
+pub fn clip_space_line<P: Copy>(
+    a: &vector::Vector4,
+    b: &vector::Vector4,
+    image: &mut image::Image<P>,
+    color: &P)
+{
+    if let Some((a, b)) = clipping::clip_line(a, b) {
+        let viewport = matrix::Matrix4::viewport(0.0, 0.0, image.get_width() as f64, image.get_height() as f64);
+
+        line_from_vertices((viewport * a).to_point(), (viewport * b).to_point(), image, color);
+    }
+}
+
+// Twice the area of a polygon on the screen (the shoelace formula). Positive when the corners go
+// counter-clockwise, given y goes up.
+fn signed_area(corners: &[point::Point3D]) -> f64 {
+    (0..corners.len())
+        .map(|i| {
+            let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+
+            a.x * b.y - b.x * a.y
+        })
+        .sum()
+}
+
+// Window coordinates get snapped to a grid of 1/256th of a pixel and the coverage math is done
+// in integers on that grid. With floats, whether a pixel center sitting right on an edge is
+// inside or not depends on rounding, which can come out differently for the two triangles
+// sharing the edge: the pixel gets drawn twice or not at all. Integers don't round.
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+
+// A point on the subpixel grid
+type Fixed = (i64, i64);
+
+// The edge function: twice the signed area of the triangle a, b, p. Positive when p is to the
+// left of the edge going from a to b (with y going up).
+// For a counter-clockwise triangle, p is inside when it's to the left of all three edges.
+fn edge_function(a: Fixed, b: Fixed, p: Fixed) -> i64 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+// The top-left fill rule: a pixel center that lands exactly on an edge only belongs to the
+// triangle if the edge is a top or a left edge. Two triangles sharing an edge are on opposite
+// sides of it, so exactly one of them gets the pixel.
+// Going counter-clockwise with y going up, the inside is to the left. So a left edge goes down,
+// and a top edge is flat and goes to the left.
+fn is_top_left(a: Fixed, b: Fixed) -> bool {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+
+    dy < 0 || (dy == 0 && dx < 0)
+}
+
+// Rasterize a triangle that has already been clipped.
+// Pixels are sampled at their centers: pixel (x, y) is covered when (x + 0.5, y + 0.5) is inside
+// the triangle, with ties going by the top-left rule. Triangles sharing edges cover every pixel
+// exactly once, without gaps or overdraw.
+fn rasterize<S, C, O>(
+    vertices: &[VertexOutput<S::Varyings>; 3],
+    shader: &S,
+    target: &mut framebuffer::Framebuffer<C>)
+where
+    S: FragmentShader<O>,
+    C: framebuffer::ColorTarget<O>
+{
+    // Nothing to draw into
+    if target.get_width() == 0 || target.get_height() == 0 {
+        return;
+    }
+
+    let width = target.get_width() as f64;
+    let height = target.get_height() as f64;
+
+    let viewport = matrix::Matrix4::viewport(0.0, 0.0, width, height);
+
+    // Viewport transform followed by the perspective divide.
+    // The viewport doesn't touch w, so doing the divide last gives the same result.
+    let to_screen = |position: &vector::Vector4| (viewport * *position).to_point();
+    let to_fixed = |point: &point::Point3D| {
+        ((point.x * SUBPIXEL_ONE as f64).round() as i64, (point.y * SUBPIXEL_ONE as f64).round() as i64)
+    };
+
+    let screen = [
+        to_screen(&vertices[0].position),
+        to_screen(&vertices[1].position),
+        to_screen(&vertices[2].position)];
+
+    let snapped = screen.map(|point| to_fixed(&point));
+    let area = edge_function(snapped[0], snapped[1], snapped[2]);
+
+    // Degenerate triangles (all three corners on a line) don't cover anything
+    if area == 0 {
+        return;
+    }
+
+    // Culling has already happened, so from here on we don't care which way the triangle faces.
+    // Turning clockwise triangles around means there's only one case to deal with.
+    let order = if area > 0 { [0, 1, 2] } else { [0, 2, 1] };
+
+    let corners = order.map(|i| snapped[i]);
+    let varyings = order.map(|i| vertices[i].varyings);
+
+    // Barycentric coordinates found in screen space are not the right weights for the varyings
+    // once a perspective projection is involved: equal steps on the screen are not equal steps on
+    // the triangle in 3D, because parts further away get squished.
+    // What *does* vary linearly in screen space is attribute/w and 1/w (w being the clip-space w,
+    // which is proportional to the distance from the camera). So we interpolate those and divide
+    // one by the other to get the attribute back.
+    // Depth is the exception: z was already divided by w, so it is linear in screen space as is.
+    let inverse_w = order.map(|i| 1.0 / vertices[i].position.w);
+
+    // Edge i is the one across from corner i
+    let edges = [(corners[1], corners[2]), (corners[2], corners[0]), (corners[0], corners[1])];
 
+    // Whether a pixel is covered is decided on the grid, but the varyings get interpolated
+    // between the corners as they were before snapping. Snapping moves them by up to half a
+    // step of the grid, and the varyings shouldn't move along with them.
+    // The edge function of edge i divided by the area of the whole triangle is the barycentric
+    // coordinate of corner i.
+    // The exception are slivers about as thin as the grid step: snapping changes their shape a
+    // lot, and pixels the snapped triangle covers can be way outside of the original one. Their
+    // weights would go into the thousands. Then the snapped corners are all there is.
+    let float_edge = |a: &point::Point3D, b: &point::Point3D, x: f64, y: f64| (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x);
 
-            if u >= 0.0 && v >= 0.0 && u + v <= 1.0 {
-                if zbuffer[index] < interpolated_z_value {
-                    zbuffer[index] = interpolated_z_value;
+    let mut points = order.map(|i| screen[i]);
+    let mut float_area = float_edge(&points[0], &points[1], points[2].x, points[2].y);
+    let snapped_area = area.abs() as f64 / (SUBPIXEL_ONE * SUBPIXEL_ONE) as f64;
 
-                    let interpolated_texture_x = (w * t0.x + u * t1.x + v * t2.x);
-                    let interpolated_texture_y = (w * t0.y + u * t1.y + v * t2.y);
+    if (float_area - snapped_area).abs() > snapped_area * 0.01 {
+        for (point, corner) in points.iter_mut().zip(corners.iter()) {
+            point.x = corner.0 as f64 / SUBPIXEL_ONE as f64;
+            point.y = corner.1 as f64 / SUBPIXEL_ONE as f64;
+        }
+
+        float_area = float_edge(&points[0], &points[1], points[2].x, points[2].y);
+    }
+
+    // Moving pixels that land right on an edge that isn't top-left outside, without touching
+    // anything else: edge functions are integers, so there's nothing between 0 and -1.
+    let bias = edges.map(|(a, b)| if is_top_left(a, b) { 0 } else { -1 });
+
+    // Clipping keeps the triangle on the screen, give or take some rounding. We stay inside of
+    // the image either way.
+    let last_x = width as usize - 1;
+    let last_y = height as usize - 1;
+
+    // The bounding box, in pixels whose centers are inside of it
+    let min_x = corners.iter().map(|corner| corner.0).min().unwrap();
+    let max_x = corners.iter().map(|corner| corner.0).max().unwrap();
+    let min_y = corners.iter().map(|corner| corner.1).min().unwrap();
+    let max_y = corners.iter().map(|corner| corner.1).max().unwrap();
+
+    let first_pixel = |min: i64| (min - SUBPIXEL_HALF + SUBPIXEL_ONE - 1).div_euclid(SUBPIXEL_ONE).max(0) as usize;
+    let last_pixel = |max: i64, last: usize| (max - SUBPIXEL_HALF).div_euclid(SUBPIXEL_ONE).min(last as i64);
+
+    let (end_x, end_y) = (last_pixel(max_x, last_x), last_pixel(max_y, last_y));
+
+    if end_x < 0 || end_y < 0 {
+        return;
+    }
+
+    let (end_x, end_y) = (end_x as usize, end_y as usize);
+
+    let coverage = |x: usize, y: usize| {
+        let center = (x as i64 * SUBPIXEL_ONE + SUBPIXEL_HALF, y as i64 * SUBPIXEL_ONE + SUBPIXEL_HALF);
+        let values = edges.map(|(a, b)| edge_function(a, b, center));
+
+        let inside = (0..3).all(|i| values[i] + bias[i] >= 0);
+
+        let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+        let weights = [
+            float_edge(&points[1], &points[2], x, y) / float_area,
+            float_edge(&points[2], &points[0], x, y) / float_area,
+            float_edge(&points[0], &points[1], x, y) / float_area];
+
+        // A pixel the snapped triangle covers can still be a hair outside of the one before
+        // snapping, with a weight a bit below 0. Shaded pixels mustn't get anything from past
+        // the corners. Helper pixels are left alone, they're only there for the derivatives.
+        if inside && weights.iter().any(|&weight| weight < 0.0) {
+            let weights = weights.map(|weight| weight.max(0.0));
+            let sum = weights[0] + weights[1] + weights[2];
+
+            return (inside, weights.map(|weight| weight / sum));
+        }
+
+        (inside, weights)
+    };
 
-                    let value_x = interpolated_texture_x * texture_width;
-                    let value_y = interpolated_texture_y * texture_height;
+    // Pixels get processed in 2x2 blocks ("quads"), the same way GPUs do it.
+    // Within a quad, we can tell how fast the varyings change from one pixel to the next by
+    // looking at the neighbours. Texture sampling needs this to know how much of the texture a
+    // pixel covers (see `Sampler::sample_grad`).
+    // To always have neighbours to look at, the varyings get computed for the whole quad, even for
+    // the pixels that are outside of the triangle. Those "helper" pixels don't get shaded.
+    let start_x = first_pixel(min_x) & !1;
+    let start_y = first_pixel(min_y) & !1;
 
-                    let mut interpolated_color = texture.get(value_x as u16, value_y as u16);
+    for quad_y in (start_y..=end_y).step_by(2) {
+        for quad_x in (start_x..=end_x).step_by(2) {
+            // Bottom left, bottom right, top left, top right
+            let pixels = [(quad_x, quad_y), (quad_x + 1, quad_y), (quad_x, quad_y + 1), (quad_x + 1, quad_y + 1)];
 
-                    image.set(p_x as u16, p_y as u16, &interpolated_color).unwrap();
+            let samples = pixels.map(|(x, y)| coverage(x, y));
+
+            if !samples.iter().any(|(inside, _)| *inside) {
+                continue;
+            }
+
+            let quad_varyings = samples.map(|(_, barycentric)| {
+                let weights = [
+                    barycentric[0] * inverse_w[0],
+                    barycentric[1] * inverse_w[1],
+                    barycentric[2] * inverse_w[2]];
+                let interpolated_inverse_w = weights[0] + weights[1] + weights[2];
+                let weights = [
+                    weights[0] / interpolated_inverse_w,
+                    weights[1] / interpolated_inverse_w,
+                    weights[2] / interpolated_inverse_w];
+
+                Varying::barycentric(&varyings, weights)
+            });
+
+            // One derivative per quad, which is what GPUs call "coarse" derivatives
+            let ddx = quad_varyings[1].add(&quad_varyings[0].scale(-1.0));
+            let ddy = quad_varyings[2].add(&quad_varyings[0].scale(-1.0));
+
+            for i in 0..4 {
+                let (x, y) = pixels[i];
+                let (inside, barycentric) = samples[i];
+
+                if !inside || x > last_x || y > last_y {
+                    continue;
                 }
+
+                // Rounding can still take it a hair past the near or far plane
+                let interpolated_z_value = (barycentric[0] * points[0].z + barycentric[1] * points[1].z + barycentric[2] * points[2].z)
+                    .clamp(0.0, 1.0);
+
+                if target.test(x, y, interpolated_z_value) {
+                    let input = FragmentInput {
+                        frag_coord: point::Point3D { x: x as f64 + 0.5, y: y as f64 + 0.5, z: interpolated_z_value },
+                        varyings: quad_varyings[i],
+                        ddx,
+                        ddy,
+                    };
+
+                    if let Fragment::Color(color) = shader.fragment(&input) {
+                        target.write(x, y, interpolated_z_value, &color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::cell::{Cell, RefCell};
+
+    use crate::color::Color32;
+    use crate::framebuffer::{Framebuffer, StencilMode};
+    use crate::drawing::{self, CullMode, FrontFace, RasterizerState};
+    use crate::image::Image;
+    use crate::matrix::Matrix4;
+    use crate::point::{Point2D, Point3D};
+    use crate::shader::{Fragment, FragmentInput, FragmentShader, VertexOutput, VertexShader};
+    use crate::test_util;
+    use crate::vector::{Vector3D, Vector4};
+
+    const SIZE: usize = 200;
+    const CHECKS: f64 = 8.0;
+
+    // A floor quad at y = -1 stretching away from a camera sitting at the origin, textured with
+    // a procedural checkerboard. Every fragment's texture coordinate gets recorded so it can be
+    // checked against the exact answer.
+    struct CheckerboardShader {
+        projection: Matrix4,
+        fragments: RefCell<Vec<(Point3D, Point2D)>>,
+    }
+
+    impl VertexShader for CheckerboardShader {
+        type Input = (Point3D, Point2D);
+        type Varyings = Point2D;
+
+        fn vertex(&self, input: &Self::Input) -> VertexOutput<Point2D> {
+            VertexOutput { position: self.projection * Vector4::from(input.0), varyings: input.1 }
+        }
+    }
+
+    impl FragmentShader for CheckerboardShader {
+        type Varyings = Point2D;
+
+        fn fragment(&self, input: &FragmentInput<Point2D>) -> Fragment {
+            self.fragments.borrow_mut().push((input.frag_coord, input.varyings));
+
+            Fragment::Color(checker(&input.varyings))
+        }
+    }
+
+    fn checker(uv: &Point2D) -> Color32 {
+        if ((uv.x * CHECKS).floor() + (uv.y * CHECKS).floor()) as i64 % 2 == 0 {
+            Color32::new(255, 255, 255, 255)
+        }
+        else {
+            Color32::new(0, 0, 0, 255)
+        }
+    }
+
+    // Shoot a ray from the camera through the pixel and see where it hits the floor.
+    // With a 90 degree field of view and a square image, a pixel at normalized device coordinates
+    // (x, y) looks along the direction (x, y, -1).
+    fn analytic_uv(frag_coord: &Point3D) -> Point2D {
+        let ndc_x = frag_coord.x / SIZE as f64 * 2.0 - 1.0;
+        let ndc_y = frag_coord.y / SIZE as f64 * 2.0 - 1.0;
+
+        let t = -1.0 / ndc_y;
+        let (x, z) = (t * ndc_x, -t);
+
+        Point2D { x: x + 0.5, y: (-z - 1.5) / 4.5 }
+    }
+
+    #[test]
+    fn test_perspective_correct_checkerboard() {
+        let shader = CheckerboardShader {
+            projection: Matrix4::perspective(90.0_f64.to_radians(), 1.0, 0.1, 100.0),
+            fragments: RefCell::new(Vec::new()),
+        };
+
+        let corner = |x: f64, z: f64, u: f64, v: f64| (Point3D { x, y: -1.0, z }, Point2D { x: u, y: v });
+        let near_left = corner(-0.5, -1.5, 0.0, 0.0);
+        let near_right = corner(0.5, -1.5, 1.0, 0.0);
+        let far_right = corner(0.5, -6.0, 1.0, 1.0);
+        let far_left = corner(-0.5, -6.0, 0.0, 1.0);
+
+        let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+
+        drawing::draw_triangles(
+            &shader,
+            &shader,
+            vec![[near_left, near_right, far_right], [near_left, far_right, far_left]],
+            &RasterizerState::default(),
+            &mut framebuffer);
+
+        let fragments = shader.fragments.borrow();
+        assert!(fragments.len() > 1000);
+
+        for (frag_coord, uv) in fragments.iter() {
+            let expected = analytic_uv(frag_coord);
+
+            assert!((uv.x - expected.x).abs() < 1e-6 && (uv.y - expected.y).abs() < 1e-6,
+                "At ({}, {}) got uv ({}, {}), expected ({}, {})",
+                frag_coord.x, frag_coord.y, uv.x, uv.y, expected.x, expected.y);
+
+            // Right on the border between two squares either color is fine
+            let on_border = |t: f64| ((t * CHECKS).round() - t * CHECKS).abs() < 1e-4;
+
+            if !on_border(expected.x) && !on_border(expected.y) {
+                assert_eq!(checker(uv).get_pixel_value(), checker(&expected).get_pixel_value());
             }
+        }
+    }
 
-            p_y += 1.0;
+    struct SolidShader;
+
+    impl FragmentShader for SolidShader {
+        type Varyings = ();
+
+        fn fragment(&self, _input: &FragmentInput<()>) -> Fragment {
+            Fragment::Color(Color32::new(255, 0, 0, 255))
         }
+    }
+
+    #[test]
+    fn test_partially_visible_triangle_is_clipped() {
+        let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+
+        // Two corners are off the screen and one is behind the camera
+        let vertices = [
+            VertexOutput { position: Vector4::new(-3.0, -0.5, 0.0, 1.0), varyings: () },
+            VertexOutput { position: Vector4::new(4.0, -0.5, 0.0, 1.0), varyings: () },
+            VertexOutput { position: Vector4::new(0.0, 2.0, 0.5, -0.2), varyings: () }];
+
+        drawing::triangle(&vertices, &SolidShader, &RasterizerState::default(), &mut framebuffer);
+
+        drawing::clip_space_line(
+            &Vector4::new(-5.0, -5.0, 0.0, 1.0),
+            &Vector4::new(5.0, 5.0, 0.0, 1.0),
+            &mut framebuffer.color,
+            &Color32::new(0, 255, 0, 255));
+
+        // The middle of the screen is covered by the triangle
+        assert_eq!(framebuffer.color.get(SIZE / 2, SIZE * 3 / 8), Color32::new(255, 0, 0, 255));
+    }
+
+    // Only draws the right half of the screen
+    struct HalfShader;
+
+    impl FragmentShader for HalfShader {
+        type Varyings = ();
+
+        fn fragment(&self, input: &FragmentInput<()>) -> Fragment {
+            if input.frag_coord.x < SIZE as f64 / 2.0 {
+                Fragment::Discard
+            } else {
+                Fragment::Color(Color32::new(255, 0, 0, 255))
+            }
+        }
+    }
+
+    #[test]
+    fn test_discarded_fragments_are_not_written() {
+        let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+
+        let corner = |x: f64, y: f64| VertexOutput { position: Vector4::new(x, y, 0.0, 1.0), varyings: () };
+
+        drawing::triangle(&[corner(-0.9, -0.9), corner(0.9, -0.9), corner(0.0, 0.9)], &HalfShader, &RasterizerState::default(), &mut framebuffer);
+
+        let depth = framebuffer.depth.as_ref().unwrap();
+        let (left, right, y) = (SIZE / 2 - 10, SIZE / 2 + 10, SIZE / 2);
+
+        // Discarded: the color and the depth are what they were cleared to
+        assert_eq!(framebuffer.color.get(left, y), Color32::new(0, 0, 0, 255));
+        assert_eq!(depth.get(left, y), 1.0);
+
+        assert_eq!(framebuffer.color.get(right, y), Color32::new(255, 0, 0, 255));
+        assert_eq!(depth.get(right, y), 0.5);
+    }
+
+    // Passes the x coordinate in normalized device coordinates along, and checks its derivatives
+    struct DerivativeShader;
+
+    impl FragmentShader for DerivativeShader {
+        type Varyings = f64;
+
+        fn fragment(&self, input: &FragmentInput<f64>) -> Fragment {
+            assert!((input.ddx - 2.0 / SIZE as f64).abs() < 1e-9);
+            assert!(input.ddy.abs() < 1e-9);
+
+            Fragment::Color(Color32::new(255, 255, 255, 255))
+        }
+    }
+
+    #[test]
+    fn test_quad_derivatives() {
+        let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+
+        let corner = |x: f64, y: f64| VertexOutput { position: Vector4::new(x, y, 0.0, 1.0), varyings: x };
+
+        drawing::triangle(&[corner(-0.9, -0.9), corner(0.9, -0.9), corner(0.0, 0.9)], &DerivativeShader, &RasterizerState::default(), &mut framebuffer);
+
+        assert_eq!(framebuffer.color.get(SIZE / 2, SIZE / 2), Color32::new(255, 255, 255, 255));
+    }
+
+    // Passes the position straight through, counting how often it gets called
+    struct CountingShader {
+        calls: Cell<usize>,
+    }
+
+    impl VertexShader for CountingShader {
+        type Input = Vector4;
+        type Varyings = ();
+
+        fn vertex(&self, input: &Vector4) -> VertexOutput<()> {
+            self.calls.set(self.calls.get() + 1);
+
+            VertexOutput { position: *input, varyings: () }
+        }
+    }
+
+    #[test]
+    fn test_indexed_drawing_shades_each_vertex_once() {
+        let corners = [
+            Vector4::new(-0.5, -0.5, 0.0, 1.0),
+            Vector4::new(0.5, -0.5, 0.0, 1.0),
+            Vector4::new(0.5, 0.5, 0.0, 1.0),
+            Vector4::new(-0.5, 0.5, 0.0, 1.0)];
+
+        let shader = CountingShader { calls: Cell::new(0) };
+        let mut indexed = Framebuffer::rgba(SIZE, SIZE);
+
+        drawing::draw_indexed(&shader, &SolidShader, &corners, &[0, 1, 2, 0, 2, 3], &RasterizerState::default(), &mut indexed);
+
+        assert_eq!(shader.calls.get(), 4);
+
+        let mut unindexed = Framebuffer::rgba(SIZE, SIZE);
+        let triangles = vec![[corners[0], corners[1], corners[2]], [corners[0], corners[2], corners[3]]];
+
+        drawing::draw_triangles(&shader, &SolidShader, triangles, &RasterizerState::default(), &mut unindexed);
+
+        assert_eq!(shader.calls.get(), 4 + 6);
+        assert!(indexed.color.pixels() == unindexed.color.pixels());
+    }
+
+    #[test]
+    fn test_culling() {
+        let corner = |x: f64, y: f64, w: f64| VertexOutput { position: Vector4::new(x * w, y * w, 0.0, w), varyings: () };
+
+        // Counter-clockwise on the screen, and the same triangle the other way round
+        let counter_clockwise = [corner(-0.5, -0.5, 1.0), corner(0.5, -0.5, 1.0), corner(0.0, 0.5, 1.0)];
+        let clockwise = [counter_clockwise[0], counter_clockwise[2], counter_clockwise[1]];
+        // Counter-clockwise too, but big and with one corner behind the camera. Its corners
+        // projected as they are would go round the other way.
+        let behind = [corner(-0.5, -0.5, 1.0), corner(0.5, -0.5, 1.0), VertexOutput { position: Vector4::new(0.0, 2.0, 0.0, -0.5), varyings: () }];
+
+        let drawn = |triangle: &[VertexOutput<()>; 3], cull_mode, front_face| {
+            let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+            drawing::triangle(triangle, &SolidShader, &RasterizerState { cull_mode, front_face }, &mut framebuffer);
+
+            framebuffer.color.get(SIZE / 2, SIZE / 2) == Color32::new(255, 0, 0, 255)
+        };
+
+        for triangle in [&counter_clockwise, &clockwise, &behind] {
+            assert!(drawn(triangle, CullMode::None, FrontFace::CounterClockwise));
+            assert!(drawn(triangle, CullMode::None, FrontFace::Clockwise));
+        }
+
+        for triangle in [&counter_clockwise, &behind] {
+            assert!(drawn(triangle, CullMode::Back, FrontFace::CounterClockwise));
+            assert!(!drawn(triangle, CullMode::Front, FrontFace::CounterClockwise));
+            assert!(!drawn(triangle, CullMode::Back, FrontFace::Clockwise));
+            assert!(drawn(triangle, CullMode::Front, FrontFace::Clockwise));
+        }
+
+        assert!(!drawn(&clockwise, CullMode::Back, FrontFace::CounterClockwise));
+        assert!(drawn(&clockwise, CullMode::Front, FrontFace::CounterClockwise));
+        assert!(drawn(&clockwise, CullMode::Back, FrontFace::Clockwise));
+    }
+
+    #[test]
+    fn test_culling_a_closed_mesh() {
+        // A cube with its faces going counter-clockwise seen from outside, looked at from a corner
+        let view_projection = Matrix4::perspective(60.0_f64.to_radians(), 1.0, 0.1, 100.0)
+            * Matrix4::look_at(&Point3D { x: 3.0, y: 2.5, z: 4.0 }, &Point3D::default(), &Vector3D::new(0.0, 1.0, 0.0));
+
+        let corners: Vec<Vector4> = (0..8)
+            .map(|i| {
+                let side = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+
+                view_projection * Vector4::new(side(1), side(2), side(4), 1.0)
+            })
+            .collect();
+
+        let faces = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3]];
+        let indices: Vec<usize> = faces.iter().flat_map(|&[a, b, c, d]| [a, b, c, a, c, d]).collect();
+
+        // How often every pixel got drawn, and the depth that was left. Without culling we go
+        // without the depth test, or the far side wouldn't get counted.
+        let draw = |cull_mode, front_face| {
+            let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+
+            if cull_mode == CullMode::None {
+                framebuffer.depth = None;
+            }
+
+            framebuffer.stencil = Some(Image::new(SIZE, SIZE, 0));
+            framebuffer.stencil_mode = StencilMode::Increment;
+
+            let shader = CountingShader { calls: Cell::new(0) };
+            drawing::draw_indexed(&shader, &SolidShader, &corners, &indices, &RasterizerState { cull_mode, front_face }, &mut framebuffer);
+
+            (framebuffer.stencil.unwrap(), framebuffer.depth)
+        };
+
+        let (both, _) = draw(CullMode::None, FrontFace::CounterClockwise);
+        let (front, front_depth) = draw(CullMode::Back, FrontFace::CounterClockwise);
+        let (back, back_depth) = draw(CullMode::Front, FrontFace::CounterClockwise);
+
+        // Either way round, the cube covers every pixel once, and without culling twice
+        let covered = |counts: &Image<u8>| counts.pixels().iter().filter(|&&count| count > 0).count();
+
+        assert!(covered(&front) > 1000);
+        assert!(front.pixels().iter().chain(back.pixels()).all(|&count| count <= 1));
+        assert_eq!(covered(&back), covered(&front));
+        assert!(both.pixels().iter().zip(front.pixels()).all(|(&both, &front)| both == 2 * front));
+
+        // Culling the front faces leaves the far side of the cube
+        let (front_depth, back_depth) = (front_depth.unwrap(), back_depth.unwrap());
+        assert!(back_depth.get(SIZE / 2, SIZE / 2) > front_depth.get(SIZE / 2, SIZE / 2));
+
+        // With clockwise triangles in front, the cube looks like it's inside out
+        let (_, depth) = draw(CullMode::Front, FrontFace::Clockwise);
+        assert!(depth == Some(front_depth));
+
+        let (_, depth) = draw(CullMode::Back, FrontFace::Clockwise);
+        assert!(depth == Some(back_depth));
+    }
+
+    // A framebuffer that counts how often every pixel gets drawn, and nothing else
+    fn overdraw_counter() -> Framebuffer<()> {
+        let mut framebuffer = Framebuffer::new(SIZE, SIZE, ());
+        framebuffer.depth = None;
+        framebuffer.stencil = Some(Image::new(SIZE, SIZE, 0));
+        framebuffer.stencil_mode = StencilMode::Increment;
+
+        framebuffer
+    }
+
+    // A corner in window coordinates
+    fn window(x: f64, y: f64) -> VertexOutput<()> {
+        let ndc = |value: f64| value / SIZE as f64 * 2.0 - 1.0;
+
+        VertexOutput { position: Vector4::new(ndc(x), ndc(y), 0.0, 1.0), varyings: () }
+    }
+
+    #[test]
+    fn test_watertight() {
+        // A grid of quads covering the whole screen, with the inner corners moved around: some
+        // land on pixel centers, some on pixel corners, some anywhere. Every other quad gets
+        // split along the other diagonal, and every other triangle goes round the other way.
+        const CELLS: usize = 12;
+        let step = SIZE as f64 / CELLS as f64;
+
+        let mut random = {
+            let mut random = test_util::random(0x9e37_79b9_7f4a_7c15);
+            move || random() - 0.5
+        };
+
+        let mut grid = vec![vec![(0.0, 0.0); CELLS + 1]; CELLS + 1];
+
+        for (j, row) in grid.iter_mut().enumerate() {
+            for (i, corner) in row.iter_mut().enumerate() {
+                let (x, y) = (i as f64 * step, j as f64 * step);
+
+                *corner = if i == 0 || j == 0 || i == CELLS || j == CELLS {
+                    (x, y)
+                } else {
+                    let (x, y) = (x + random() * step * 0.8, y + random() * step * 0.8);
+
+                    match (i + j) % 3 {
+                        0 => (x.floor() + 0.5, y.floor() + 0.5),
+                        1 => (x.round(), y.round()),
+                        _ => (x, y),
+                    }
+                };
+            }
+        }
+
+        let mut framebuffer = overdraw_counter();
+        let corner = |i: usize, j: usize| window(grid[j][i].0, grid[j][i].1);
+
+        for j in 0..CELLS {
+            for i in 0..CELLS {
+                let (a, b, c, d) = (corner(i, j), corner(i + 1, j), corner(i + 1, j + 1), corner(i, j + 1));
+
+                let triangles = if (i + j) % 2 == 0 { [[a, b, c], [a, d, c]] } else { [[a, b, d], [b, c, d]] };
+
+                for triangle in triangles.iter() {
+                    drawing::triangle(triangle, &SolidShader, &RasterizerState::default(), &mut framebuffer);
+                }
+            }
+        }
+
+        let counts = framebuffer.stencil.unwrap();
+        assert!(counts.pixels().iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_empty_framebuffer() {
+        for (width, height) in [(0, 0), (0, 8), (8, 0)] {
+            let mut framebuffer = Framebuffer::rgba(width, height);
+
+            drawing::triangle(&[window(0.0, 0.0), window(1.0, 0.0), window(0.0, 1.0)], &SolidShader, &RasterizerState::default(), &mut framebuffer);
+            drawing::triangle(
+                &[window(-100.0, -100.0), window(300.0, -100.0), window(0.0, 300.0)],
+                &SolidShader,
+                &RasterizerState::default(),
+                &mut framebuffer);
+
+            assert!(framebuffer.color.pixels().is_empty());
+        }
+    }
+
+    // Records the depth of every fragment next to a varying that holds the same thing
+    struct DepthRecorder {
+        depths: RefCell<Vec<(f64, f64)>>,
+    }
+
+    impl FragmentShader for DepthRecorder {
+        type Varyings = f64;
+
+        fn fragment(&self, input: &FragmentInput<f64>) -> Fragment {
+            self.depths.borrow_mut().push((input.frag_coord.z, input.varyings));
+
+            Fragment::Color(Color32::new(255, 0, 0, 255))
+        }
+    }
+
+    #[test]
+    fn test_slivers_stay_between_their_corners() {
+        // A corner in window coordinates at a depth between 0 and 1
+        let corner = |x: f64, y: f64, depth: f64| {
+            let ndc = |value: f64| value / SIZE as f64 * 2.0 - 1.0;
+
+            VertexOutput { position: Vector4::new(ndc(x), ndc(y), depth * 2.0 - 1.0, 1.0), varyings: depth }
+        };
+
+        let mut random = test_util::random(0x853c_49e6_748f_ea9b);
+
+        // This one used to come out at a depth of more than a thousand
+        let mut triangles = vec![[(33.32, 27.35, 0.1), (34.22, 23.63, 0.9), (34.67, 21.81, 0.5)]];
+
+        // Nearly flat: the third corner goes a tiny bit off the line through the other two
+        for _ in 0..2000 {
+            let (x, y) = (10.0 + random() * 100.0, 10.0 + random() * 100.0);
+            let (dx, dy) = (random() * 40.0 - 20.0, random() * 40.0 - 20.0);
+            let (t, off) = (random() * 2.0, (random() - 0.5) * 0.02);
+
+            triangles.push([
+                (x, y, random()),
+                (x + dx, y + dy, random()),
+                (x + dx * t - dy * off, y + dy * t + dx * off, random())]);
+        }
+
+        let shader = DepthRecorder { depths: RefCell::new(Vec::new()) };
+        let mut framebuffer = Framebuffer::rgba(SIZE, SIZE);
+        framebuffer.depth = None;
+
+        for triangle in triangles.iter() {
+            shader.depths.borrow_mut().clear();
+
+            drawing::triangle(&triangle.map(|(x, y, depth)| corner(x, y, depth)), &shader, &RasterizerState::default(), &mut framebuffer);
+
+            let min = triangle.iter().map(|corner| corner.2).fold(f64::INFINITY, f64::min) - 1e-9;
+            let max = triangle.iter().map(|corner| corner.2).fold(f64::NEG_INFINITY, f64::max) + 1e-9;
+
+            for &(depth, varying) in shader.depths.borrow().iter() {
+                assert!(depth >= min && depth <= max, "depth {} outside of {:?}", depth, triangle);
+                assert!(varying >= min && varying <= max, "varying {} outside of {:?}", varying, triangle);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lines_stay_inside_the_image() {
+        for (width, height) in [(0, 0), (0, 8), (8, 0)] {
+            let mut image = Image::new(width, height, 0_u8);
+
+            drawing::line(0, 0, 5, 5, &mut image, &1);
+            drawing::line_from_vertices(Point3D::default(), Point3D { x: 3.0, y: 4.0, z: 0.0 }, &mut image, &1);
+            drawing::clip_space_line(&Vector4::new(-1.0, -1.0, 0.0, 1.0), &Vector4::new(1.0, 1.0, 0.0, 1.0), &mut image, &1);
+
+            assert!(image.pixels().is_empty());
+        }
+
+        // Off the edge of the image: the part that's on it still gets drawn
+        let mut image = Image::new(4, 4, 0_u8);
+        drawing::line(0, 2, 9, 2, &mut image, &1);
+
+        assert!((0..4).all(|x| image.get(x, 2) == 1));
+    }
+
+    #[test]
+    fn test_top_left_rule() {
+        // A square from 2.5 to 6.5 both ways: all of its edges go right through pixel centers.
+        // The left and top edges keep theirs, the right and bottom ones don't.
+        let square = [window(2.5, 2.5), window(6.5, 2.5), window(6.5, 6.5), window(2.5, 6.5)];
+
+        let mut framebuffer = overdraw_counter();
+
+        drawing::triangle(&[square[0], square[1], square[2]], &SolidShader, &RasterizerState::default(), &mut framebuffer);
+        drawing::triangle(&[square[0], square[2], square[3]], &SolidShader, &RasterizerState::default(), &mut framebuffer);
+
+        let counts = framebuffer.stencil.unwrap();
+
+        for y in 0..10 {
+            for x in 0..10 {
+                let expected = if (2..6).contains(&x) && (3..7).contains(&y) { 1 } else { 0 };
+
+                assert_eq!(counts.get(x, y), expected, "at ({}, {})", x, y);
+            }
+        }
+
+        // A sliver much thinner than a pixel only covers the centers it goes over. Exactly on
+        // the centers of column 20 it's a left edge, so it gets those.
+        let mut framebuffer = overdraw_counter();
+
+        drawing::triangle(&[window(20.5, 10.0), window(20.6, 10.0), window(20.5, 30.0)], &SolidShader, &RasterizerState::default(), &mut framebuffer);
+
+        let counts = framebuffer.stencil.unwrap();
+        let covered: Vec<(usize, usize)> = (0..SIZE * SIZE)
+            .map(|i| (i % SIZE, i / SIZE))
+            .filter(|&(x, y)| counts.get(x, y) > 0)
+            .collect();
 
-        p_x += 1.0;
+        assert_eq!(covered, (10..30).map(|y| (20, y)).collect::<Vec<_>>());
     }
 }
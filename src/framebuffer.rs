@@ -0,0 +1,211 @@
+use crate::color;
+use crate::depth;
+use crate::image;
+
+/// Something the rasterizer can write fragment shader outputs of type `O` to.
+///
+/// A single `Image<P>` takes outputs of type `P`. Tuples of targets take tuples of outputs, one
+/// for each attachment, which is how a shader writes to several images at once. `()` takes
+/// anything and throws it away, for when only the depth buffer matters.
+pub trait ColorTarget<O> {
+    fn store(&mut self, x: usize, y: usize, value: &O);
+}
+
+impl<P: Copy> ColorTarget<P> for image::Image<P> {
+    fn store(&mut self, x: usize, y: usize, value: &P) {
+        self.set(x, y, *value).unwrap();
+    }
+}
+
+impl<O> ColorTarget<O> for () {
+    fn store(&mut self, _x: usize, _y: usize, _value: &O) {}
+}
+
+macro_rules! impl_color_target_for_tuple {
+    ($($target:ident, $output:ident : $idx:tt),+) => {
+        impl<$($target: ColorTarget<$output>, $output),+> ColorTarget<($($output,)+)> for ($($target,)+) {
+            fn store(&mut self, x: usize, y: usize, value: &($($output,)+)) {
+                $(self.$idx.store(x, y, &value.$idx);)+
+            }
+        }
+    };
+}
+
+impl_color_target_for_tuple!(A, P: 0, B, Q: 1);
+impl_color_target_for_tuple!(A, P: 0, B, Q: 1, C, R: 2);
+impl_color_target_for_tuple!(A, P: 0, B, Q: 1, C, R: 2, D, S: 3);
+
+/// What to do with the stencil attachment while drawing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StencilMode {
+    /// Leave the stencil alone
+    Disabled,
+    /// Only draw where the stencil holds this value
+    Equal(u8),
+    /// Draw everywhere, setting the stencil to this value where something was drawn
+    Replace(u8),
+    /// Draw everywhere, counting how many fragments landed on each pixel. Handy to look at
+    /// overdraw.
+    Increment,
+}
+
+/// Everything the rasterizer draws into: one or more color attachments, plus an optional depth
+/// buffer and an optional stencil.
+///
+/// All attachments are expected to have the framebuffer's width and height.
+pub struct Framebuffer<C> {
+    width: usize,
+    height: usize,
+    pub color: C,
+    /// When there's no depth buffer, fragments are drawn in the order they come in
+    pub depth: Option<depth::DepthBuffer>,
+    pub stencil: Option<image::Image<u8>>,
+    pub stencil_mode: StencilMode,
+}
+
+impl<C> Framebuffer<C> {
+    /// Create a framebuffer around the given color attachments, with a depth buffer and no stencil
+    pub fn new(width: usize, height: usize, color: C) -> Framebuffer<C> {
+        Framebuffer {
+            width,
+            height,
+            color,
+            depth: Some(depth::DepthBuffer::new(width, height)),
+            stencil: None,
+            stencil_mode: StencilMode::Disabled,
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    /// Reset the depth buffer and the stencil. The color attachments are left as they are.
+    pub fn clear_depth_stencil(&mut self) {
+        if let Some(depth) = self.depth.as_mut() {
+            depth.clear();
+        }
+
+        if let Some(stencil) = self.stencil.as_mut() {
+            stencil.fill(0);
+        }
+    }
+
+    /// Run the depth and stencil tests for a fragment
+    pub fn test(&self, x: usize, y: usize, depth: f64) -> bool {
+        let depth_passes = self.depth.as_ref().map_or(true, |buffer| buffer.test(x, y, depth));
+
+        let stencil_passes = match (self.stencil_mode, self.stencil.as_ref()) {
+            (StencilMode::Equal(value), Some(stencil)) => stencil.get(x, y) == value,
+            _ => true,
+        };
+
+        depth_passes && stencil_passes
+    }
+
+    /// Write a fragment that passed the tests: color, depth and stencil
+    pub fn write<O>(&mut self, x: usize, y: usize, depth: f64, value: &O)
+    where
+        C: ColorTarget<O>
+    {
+        self.color.store(x, y, value);
+
+        if let Some(buffer) = self.depth.as_mut() {
+            buffer.write(x, y, depth);
+        }
+
+        if let Some(stencil) = self.stencil.as_mut() {
+            match self.stencil_mode {
+                StencilMode::Replace(value) => stencil.set(x, y, value).unwrap(),
+                StencilMode::Increment => stencil.set(x, y, stencil.get(x, y).saturating_add(1)).unwrap(),
+                StencilMode::Disabled | StencilMode::Equal(_) => (),
+            }
+        }
+    }
+}
+
+impl Framebuffer<image::Image<color::Color32>> {
+    /// The usual setup: a single 8-bit color attachment cleared to opaque black, with depth
+    pub fn rgba(width: usize, height: usize) -> Framebuffer<image::Image<color::Color32>> {
+        Framebuffer::new(width, height, image::Image::new(width, height, color::Color32::new(0, 0, 0, 255)))
+    }
+}
+
+impl Framebuffer<()> {
+    /// A framebuffer with nothing but a depth buffer
+    pub fn depth_only(width: usize, height: usize) -> Framebuffer<()> {
+        Framebuffer::new(width, height, ())
+    }
+
+    /// A depth-only framebuffer that draws into an existing depth buffer
+    pub fn from_depth(depth: depth::DepthBuffer) -> Framebuffer<()> {
+        Framebuffer {
+            width: depth.get_width(),
+            height: depth.get_height(),
+            color: (),
+            depth: Some(depth),
+            stencil: None,
+            stencil_mode: StencilMode::Disabled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::color::Color32;
+    use crate::drawing;
+    use crate::framebuffer::{Framebuffer, StencilMode};
+    use crate::image::Image;
+    use crate::shader::{Fragment, FragmentInput, FragmentShader, VertexOutput};
+    use crate::vector::Vector4;
+
+    // Writes a color to the first attachment and the fragment's depth to the second one
+    struct ColorAndDepthShader;
+
+    impl FragmentShader<(Color32, f64)> for ColorAndDepthShader {
+        type Varyings = ();
+
+        fn fragment(&self, input: &FragmentInput<()>) -> Fragment<(Color32, f64)> {
+            Fragment::Color((Color32::new(0, 0, 255, 255), input.frag_coord.z))
+        }
+    }
+
+    fn full_screen_triangle(z: f64) -> [VertexOutput<()>; 3] {
+        [
+            VertexOutput { position: Vector4::new(-1.0, -1.0, z, 1.0), varyings: () },
+            VertexOutput { position: Vector4::new(3.0, -1.0, z, 1.0), varyings: () },
+            VertexOutput { position: Vector4::new(-1.0, 3.0, z, 1.0), varyings: () }]
+    }
+
+    #[test]
+    fn test_multiple_attachments() {
+        let color = Image::new(4, 4, Color32::new(0, 0, 0, 255));
+        let depth = Image::new(4, 4, 1.0);
+        let mut framebuffer = Framebuffer::new(4, 4, (color, depth));
+
+        drawing::triangle(&full_screen_triangle(0.0), &ColorAndDepthShader, &drawing::RasterizerState::default(), &mut framebuffer);
+
+        assert_eq!(framebuffer.color.0.get(1, 2), Color32::new(0, 0, 255, 255));
+        assert!((framebuffer.color.1.get(1, 2) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stencil_masks_fragments() {
+        let mut framebuffer = Framebuffer::new(4, 4, ((), Image::new(4, 4, 1.0)));
+        framebuffer.depth = None;
+
+        let mut stencil = Image::new(4, 4, 0);
+        stencil.set(1, 1, 7).unwrap();
+        framebuffer.stencil = Some(stencil);
+        framebuffer.stencil_mode = StencilMode::Equal(7);
+
+        drawing::triangle(&full_screen_triangle(0.0), &ColorAndDepthShader, &drawing::RasterizerState::default(), &mut framebuffer);
+
+        assert!((framebuffer.color.1.get(1, 1) - 0.5).abs() < 1e-9);
+        assert_eq!(framebuffer.color.1.get(2, 2), 1.0);
+    }
+}
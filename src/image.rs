@@ -0,0 +1,112 @@
+use crate::color;
+use crate::tga;
+use crate::vector;
+
+/// A plain 2D grid of pixels, kept in memory.
+/// `P` can be anything: `Color32` for regular 8-bit images, `Vector4` for floating point color,
+/// `f64` for single channel data, etc. Images know nothing about file formats. Use `to_tga` (or
+/// another encoder) to get a file out of one.
+///
+/// Like TGA files, the first row is the bottom of the image.
+#[derive(Clone)]
+pub struct Image<P: Copy> {
+    width: usize,
+    height: usize,
+    pixels: Vec<P>,
+}
+
+impl<P: Copy> Image<P> {
+    /// Create an image where every pixel is set to `fill`
+    pub fn new(width: usize, height: usize, fill: P) -> Image<P> {
+        Image { width, height, pixels: vec![fill; width * height] }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> P {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: P) -> Result<(), String> {
+        if x >= self.width {
+            return Err(format!("Invalid x value {}. It is greater than the width of the image.", x))
+        }
+
+        if y >= self.height {
+            return Err(format!("Invalid y value {}. It is greater than the height of the image.", y))
+        }
+
+        self.pixels[y * self.width + x] = value;
+
+        Ok(())
+    }
+
+    /// Set every pixel to `value`
+    pub fn fill(&mut self, value: P) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = value);
+    }
+
+    /// All the pixels, row by row starting from the bottom
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    /// Create a new image by running `f` on every pixel
+    pub fn map<Q: Copy, F: Fn(P) -> Q>(&self, f: F) -> Image<Q> {
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|pixel| f(*pixel)).collect(),
+        }
+    }
+}
+
+impl Image<color::Color32> {
+    pub fn from_tga(file: &tga::TGAFile) -> Image<color::Color32> {
+        let (width, height) = (file.get_width(), file.get_height());
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(file.get(x, y));
+            }
+        }
+
+        Image { width: width as usize, height: height as usize, pixels }
+    }
+
+    /// Encode the image as a 32-bit uncompressed TGA file
+    pub fn to_tga(&self) -> tga::TGAFile {
+        let mut file = tga::TGAFile::new(self.width as u16, self.height as u16);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                file.set(x as u16, y as u16, &self.get(x, y)).unwrap();
+            }
+        }
+
+        file
+    }
+}
+
+impl Image<vector::Vector4> {
+    /// Convert a floating point image to 8 bits per channel.
+    /// Channels are expected to be in [0, 1]; anything outside gets clamped.
+    pub fn to_color32(&self) -> Image<color::Color32> {
+        self.map(|pixel| color::Color32::from_vector(&pixel))
+    }
+}
+
+impl Image<f64> {
+    /// Show single channel data as an opaque grayscale image: 0 is black and 1 is white.
+    /// Anything outside of [0, 1] gets clamped.
+    pub fn to_color32(&self) -> Image<color::Color32> {
+        self.map(|value| color::Color32::from_vector(&vector::Vector4::new(value, value, value, 1.0)))
+    }
+}
@@ -0,0 +1,25 @@
+pub mod tga;
+pub mod wavefront;
+pub mod drawing;
+pub mod color;
+pub mod point;
+pub mod vector;
+pub mod bytereader;
+pub mod shader;
+pub mod matrix;
+pub mod clipping;
+pub mod depth;
+pub mod image;
+pub mod framebuffer;
+pub mod sampler;
+pub mod shaders;
+pub mod triangulation;
+pub mod tokenizer;
+pub mod material;
+pub mod mesh;
+pub mod lighting;
+pub mod shadow;
+pub mod ssao;
+
+#[cfg(test)]
+mod test_util;
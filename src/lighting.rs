@@ -0,0 +1,349 @@
+use crate::point;
+use crate::shadow;
+use crate::vector;
+
+/// How a point or spot light fades with distance: the light gets divided by
+/// `constant + linear * distance + quadratic * distance²`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Attenuation {
+    /// No fading at all
+    pub fn none() -> Attenuation {
+        Attenuation { constant: 1.0, linear: 0.0, quadratic: 0.0 }
+    }
+
+    /// What physics says: inverse square
+    pub fn inverse_square() -> Attenuation {
+        Attenuation { constant: 0.0, linear: 0.0, quadratic: 1.0 }
+    }
+
+    pub fn factor(&self, distance: f64) -> f64 {
+        let divisor = self.constant + self.linear * distance + self.quadratic * distance * distance;
+
+        if divisor > 0.0 { 1.0 / divisor } else { 1.0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// Far away, like the sun: every point gets light from the same direction, at the same
+    /// strength. `direction` is the way the light travels.
+    Directional { direction: vector::Vector3D },
+    /// Shines in all directions from `position`
+    Point { position: point::Point3D, attenuation: Attenuation },
+    /// Shines from `position` in a cone around `direction`. Inside `inner_angle` (measured from
+    /// the axis, in radians) it's at full strength, and it fades out towards `outer_angle`.
+    Spot {
+        position: point::Point3D,
+        direction: vector::Vector3D,
+        inner_angle: f64,
+        outer_angle: f64,
+        attenuation: Attenuation,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Color times strength. Channels can go above 1 for bright lights.
+    pub color: vector::Vector3D,
+    /// Without a shadow map the light goes right through everything. Point lights need a
+    /// `Shadow::Cube`: a single `Shadow::Map` only sees one way, so they ignore it.
+    pub shadow: Option<shadow::Shadow>,
+}
+
+impl Light {
+    pub fn directional(direction: vector::Vector3D, color: vector::Vector3D) -> Light {
+        Light { kind: LightKind::Directional { direction }, color, shadow: None }
+    }
+
+    pub fn point(position: point::Point3D, attenuation: Attenuation, color: vector::Vector3D) -> Light {
+        Light { kind: LightKind::Point { position, attenuation }, color, shadow: None }
+    }
+
+    /// The angles are measured from `direction`, in radians. See `LightKind::Spot`.
+    pub fn spot(
+        position: point::Point3D,
+        direction: vector::Vector3D,
+        inner_angle: f64,
+        outer_angle: f64,
+        attenuation: Attenuation,
+        color: vector::Vector3D) -> Light
+    {
+        Light { kind: LightKind::Spot { position, direction, inner_angle, outer_angle, attenuation }, color, shadow: None }
+    }
+
+    // Unit vector from `position` towards the light, and how much of the light's color
+    // makes it there. A point sitting right on a point or spot light has no direction to it,
+    // so it gets nothing.
+    fn incoming(&self, position: &point::Point3D) -> (vector::Vector3D, f64) {
+        match self.kind {
+            LightKind::Directional { direction } => (-direction.normalized(), 1.0),
+            LightKind::Point { position: light, attenuation } => {
+                let to_light = light - *position;
+                let distance = to_light.length();
+
+                if distance == 0.0 {
+                    return (vector::Vector3D::default(), 0.0);
+                }
+
+                (to_light * (1.0 / distance), attenuation.factor(distance))
+            },
+            LightKind::Spot { position: light, direction, inner_angle, outer_angle, attenuation } => {
+                let to_light = light - *position;
+                let distance = to_light.length();
+
+                if distance == 0.0 {
+                    return (vector::Vector3D::default(), 0.0);
+                }
+
+                let to_light = to_light * (1.0 / distance);
+
+                // Cosines get bigger as angles get smaller, so inside the inner cone this is
+                // above 1 and outside the outer one it's below 0
+                let cosine = (-to_light).dot(&direction.normalized());
+                let (inner, outer) = (inner_angle.cos(), outer_angle.cos());
+
+                let cone = if inner > outer {
+                    ((cosine - outer) / (inner - outer)).clamp(0.0, 1.0)
+                } else if cosine >= outer {
+                    1.0
+                } else {
+                    0.0
+                };
+
+                (to_light, cone * attenuation.factor(distance))
+            },
+        }
+    }
+}
+
+/// How the highlight is computed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpecularModel {
+    /// Compares the reflected light with the direction to the eye
+    Phong,
+    /// Compares the normal with the halfway vector between the light and the eye. Cheaper, and
+    /// highlights don't get cut off at grazing angles.
+    BlinnPhong,
+}
+
+/// The light that reaches a point, split by how the surface reflects it. To get the color of
+/// the point each part gets multiplied by the matching color of the material (Ka, Kd, Ks).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Illumination {
+    pub ambient: vector::Vector3D,
+    pub diffuse: vector::Vector3D,
+    pub specular: vector::Vector3D,
+}
+
+impl Illumination {
+    pub fn color(
+        &self,
+        ambient: &vector::Vector3D,
+        diffuse: &vector::Vector3D,
+        specular: &vector::Vector3D) -> vector::Vector3D
+    {
+        self.ambient * *ambient + self.diffuse * *diffuse + self.specular * *specular
+    }
+}
+
+/// All the lights in a scene
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lighting {
+    pub lights: Vec<Light>,
+    /// Light that comes from everywhere. Stands in for all the light bouncing around the scene.
+    pub ambient: vector::Vector3D,
+    pub specular_model: SpecularModel,
+}
+
+impl Lighting {
+    pub fn new(lights: Vec<Light>, ambient: vector::Vector3D) -> Lighting {
+        Lighting { lights, ambient, specular_model: SpecularModel::BlinnPhong }
+    }
+
+    /// How much light reaches a point at `position` facing `normal` (both in world space, the
+    /// normal has to be normalized), as seen from `eye`. `shininess` is the specular exponent:
+    /// the higher, the smaller and sharper the highlights.
+    pub fn illuminate(
+        &self,
+        position: &point::Point3D,
+        normal: &vector::Vector3D,
+        eye: &point::Point3D,
+        shininess: f64) -> Illumination
+    {
+        let to_eye = (*eye - *position).normalized();
+
+        let mut result = Illumination { ambient: self.ambient, ..Illumination::default() };
+
+        for light in self.lights.iter() {
+            let (to_light, strength) = light.incoming(position);
+
+            // Lambert's cosine law: a surface gets less light the more it is tilted away from it
+            let lambert = normal.dot(&to_light);
+
+            if lambert <= 0.0 || strength <= 0.0 {
+                continue;
+            }
+
+            let strength = match (light.kind, light.shadow.as_ref()) {
+                (LightKind::Point { .. }, Some(shadow::Shadow::Map(_))) | (_, None) => strength,
+                (_, Some(shadow)) => strength * shadow.visibility(position, lambert),
+            };
+
+            let highlight = match self.specular_model {
+                SpecularModel::Phong => (-to_light).reflect(normal).dot(&to_eye),
+                SpecularModel::BlinnPhong => normal.dot(&(to_light + to_eye).normalized()),
+            };
+
+            result.diffuse = result.diffuse + light.color * (lambert * strength);
+            result.specular = result.specular + light.color * (highlight.max(0.0).powf(shininess) * strength);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::lighting::{Attenuation, Light, Lighting, SpecularModel};
+    use crate::matrix::Matrix4;
+    use crate::mesh::{Mesh, Vertex};
+    use crate::point::Point3D;
+    use crate::shadow::{spot_view_projection, CubeShadowMap, ShadowMap};
+    use crate::vector::Vector3D;
+
+    const WHITE: Vector3D = Vector3D { x: 1.0, y: 1.0, z: 1.0 };
+    const UP: Vector3D = Vector3D { x: 0.0, y: 1.0, z: 0.0 };
+    const ORIGIN: Point3D = Point3D { x: 0.0, y: 0.0, z: 0.0 };
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_directional_light() {
+        let lighting = Lighting::new(vec![Light::directional(Vector3D::new(1.0, -1.0, 0.0), WHITE)], WHITE * 0.1);
+        let eye = Point3D { x: 0.0, y: 5.0, z: 0.0 };
+
+        let lit = lighting.illuminate(&ORIGIN, &UP, &eye, 10.0);
+        assert!(close(lit.diffuse.x, 0.5_f64.sqrt()));
+        assert!(close(lit.ambient.x, 0.1));
+
+        // From below nothing gets through, apart from the ambient light
+        let lit = lighting.illuminate(&ORIGIN, &-UP, &eye, 10.0);
+        assert_eq!(lit.diffuse, Vector3D::default());
+        assert_eq!(lit.specular, Vector3D::default());
+        assert!(close(lit.ambient.y, 0.1));
+    }
+
+    #[test]
+    fn test_point_light_attenuation() {
+        let light = |attenuation| Light::point(Point3D { x: 0.0, y: 2.0, z: 0.0 }, attenuation, WHITE);
+
+        let lighting = Lighting::new(vec![light(Attenuation::inverse_square())], Vector3D::default());
+        assert!(close(lighting.illuminate(&ORIGIN, &UP, &ORIGIN, 1.0).diffuse.x, 0.25));
+
+        let lighting = Lighting::new(vec![light(Attenuation::none())], Vector3D::default());
+        assert!(close(lighting.illuminate(&ORIGIN, &UP, &ORIGIN, 1.0).diffuse.x, 1.0));
+
+        let attenuation = Attenuation { constant: 1.0, linear: 0.5, quadratic: 0.25 };
+        assert!(close(attenuation.factor(2.0), 1.0 / 3.0));
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        let spot = Light::spot(
+            Point3D { x: 0.0, y: 1.0, z: 0.0 },
+            -UP,
+            30.0_f64.to_radians(),
+            60.0_f64.to_radians(),
+            Attenuation::none(),
+            WHITE);
+        let lighting = Lighting::new(vec![spot], Vector3D::default());
+
+        // How much light gets to a point on the floor, without the cosine law
+        let strength = |x: f64| {
+            let point = Point3D { x, y: 0.0, z: 0.0 };
+            let to_light = (Point3D { x: 0.0, y: 1.0, z: 0.0 } - point).normalized();
+
+            lighting.illuminate(&point, &UP, &point, 1.0).diffuse.x / to_light.y
+        };
+
+        // Straight below and within 30 degrees: full strength
+        assert!(close(strength(0.0), 1.0));
+        assert!(close(strength(30.0_f64.to_radians().tan() - 1e-6), 1.0));
+        // Beyond 60 degrees: nothing
+        assert!(close(strength(60.0_f64.to_radians().tan() + 1e-6), 0.0));
+        // In between it fades
+        let halfway = strength(45.0_f64.to_radians().tan());
+        assert!(halfway > 0.1 && halfway < 0.9);
+    }
+
+    #[test]
+    fn test_light_on_the_surface() {
+        let lights = vec![
+            Light::point(ORIGIN, Attenuation::inverse_square(), WHITE),
+            Light::spot(ORIGIN, -UP, 0.5, 1.0, Attenuation::none(), WHITE),
+        ];
+        let lighting = Lighting::new(lights, Vector3D::default());
+        let eye = Point3D { x: 0.0, y: 5.0, z: 0.0 };
+
+        let lit = lighting.illuminate(&ORIGIN, &UP, &eye, 10.0);
+        assert_eq!(lit.diffuse, Vector3D::default());
+        assert_eq!(lit.specular, Vector3D::default());
+    }
+
+    #[test]
+    fn test_point_light_shadows() {
+        // A square right above the origin, between it and a point light
+        let corner = |x: f64, z: f64| Vertex { position: Point3D { x, y: 1.0, z }, ..Vertex::default() };
+        let blocker = Mesh::from_triangles(vec![
+            [corner(-0.5, -0.5), corner(0.5, -0.5), corner(0.5, 0.5)],
+            [corner(-0.5, -0.5), corner(0.5, 0.5), corner(-0.5, 0.5)]]);
+
+        let position = Point3D { x: 0.0, y: 2.0, z: 0.0 };
+        let mut light = Light::point(position, Attenuation::none(), WHITE);
+
+        let mut cube = CubeShadowMap::new(64, position, 0.1, 10.0);
+        cube.render(&blocker, &Matrix4::identity());
+        light.shadow = Some(cube.into());
+
+        let lighting = Lighting::new(vec![light.clone()], Vector3D::default());
+        assert_eq!(lighting.illuminate(&ORIGIN, &UP, &ORIGIN, 1.0).diffuse, Vector3D::default());
+
+        // One map looking down would have seen the square too, but it doesn't get used
+        let mut map = ShadowMap::new(64, 64, spot_view_projection(&position, &-UP, 1.0, 0.1, 10.0));
+        map.render(&blocker, &Matrix4::identity());
+        light.shadow = Some(map.into());
+
+        let lighting = Lighting::new(vec![light], Vector3D::default());
+        assert!(close(lighting.illuminate(&ORIGIN, &UP, &ORIGIN, 1.0).diffuse.x, 1.0));
+    }
+
+    #[test]
+    fn test_specular_models() {
+        // The light comes in at 45 degrees and the eye looks straight down: the mirror
+        // direction misses the eye by 45 degrees, the halfway vector misses the normal by 22.5
+        let mut lighting = Lighting::new(vec![Light::directional(Vector3D::new(1.0, -1.0, 0.0), WHITE)], Vector3D::default());
+        let eye = Point3D { x: 0.0, y: 5.0, z: 0.0 };
+
+        lighting.specular_model = SpecularModel::Phong;
+        let phong = lighting.illuminate(&ORIGIN, &UP, &eye, 4.0).specular.x;
+        assert!(close(phong, 0.5_f64.sqrt().powf(4.0)));
+
+        lighting.specular_model = SpecularModel::BlinnPhong;
+        let blinn = lighting.illuminate(&ORIGIN, &UP, &eye, 4.0).specular.x;
+        assert!(close(blinn, 22.5_f64.to_radians().cos().powf(4.0)));
+
+        // Looking right down the mirror direction gives the full highlight either way
+        let mirror = Point3D { x: 3.0, y: 3.0, z: 0.0 };
+        lighting.specular_model = SpecularModel::Phong;
+        assert!(close(lighting.illuminate(&ORIGIN, &UP, &mirror, 50.0).specular.x, 1.0));
+    }
+}
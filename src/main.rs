@@ -1,58 +1,135 @@
-mod tga;
-mod wavefront;
-mod drawing;
-mod color;
-mod point;
-mod vector;
-mod bytereader;
-
 use std::fs;
 
+use renderer::tga;
+use renderer::wavefront;
+use renderer::drawing;
+use renderer::framebuffer;
+use renderer::sampler;
+use renderer::point;
+use renderer::vector;
+use renderer::lighting;
+use renderer::material;
+use renderer::matrix;
+use renderer::mesh;
+use renderer::shaders;
+use renderer::shadow;
+use renderer::ssao;
+
 fn main() {
     let bytes = fs::read("obj/head_diffuse.tga").unwrap();
     tga::TGAFile::from_bytes(bytes).unwrap();
 }
 
 pub fn face() {
-    let model = wavefront::WaveFrontFile::new("obj/head.obj").unwrap();
-    
-    let bytes = fs::read("obj/head_diffuse.tga").unwrap();
-    let mut texture = tga::TGAFile::from_bytes(bytes).unwrap();
+    let mut model = wavefront::WaveFrontFile::new("obj/head.obj").unwrap();
+    model.generate_normals();
+
+    let load = |path: &std::path::PathBuf| {
+        let bytes = fs::read(path).unwrap();
+        sampler::MipMap::from_tga(&tga::TGAFile::from_bytes(bytes).unwrap())
+    };
+
+    // The diffuse texture and normal map of every material, from the model's material libraries
+    let textures: Vec<Option<sampler::MipMap>> = (0..model.material_count())
+        .map(|i| model.get_material(i).diffuse_map.as_ref().map(load))
+        .collect();
+
+    let normal_maps: Vec<Option<sampler::MipMap>> = (0..model.material_count())
+        .map(|i| model.get_material(i).normal_map.as_ref().map(load))
+        .collect();
 
     let width: u16 = 800;
     let height: u16 = 800;
 
-    let mut zbuffer = vec![std::f64::MIN; width as usize * height as usize];
+    let mut framebuffer = framebuffer::Framebuffer::rgba(width as usize, height as usize);
+
+    // From where the camera is, the faces turned away from it are all on the far side of the
+    // head, hidden anyway
+    let rasterizer = drawing::RasterizerState { cull_mode: drawing::CullMode::Back, ..drawing::RasterizerState::default() };
+
+    let model_matrix = matrix::Matrix4::identity();
+
+    let eye = point::Point3D { x: 1.0, y: 1.0, z: 3.0 };
+
+    let view = matrix::Matrix4::look_at(
+        &eye,
+        &point::Point3D { x: 0.0, y: 0.0, z: 0.0 },
+        &vector::Vector3D { x: 0.0, y: 1.0, z: 0.0 });
 
-    let mut image = tga::TGAFile::new(width, height);
+    let projection = matrix::Matrix4::perspective(
+        45.0_f64.to_radians(),
+        width as f64 / height as f64,
+        0.1,
+        100.0);
 
-    for i in 0..model.face_count() {
-        let face = model.get_face(i);
-        let v0 = model.get_vertex(face.vertices[0]);
-        let v1 = model.get_vertex(face.vertices[1]);
-        let v2 = model.get_vertex(face.vertices[2]);
+    let light_direction = vector::Vector3D { x: -1.0, y: -1.0, z: -1.0 };
 
-        let t0 = model.get_texture(face.textures[0]);
-        let t1 = model.get_texture(face.textures[1]);
-        let t2 = model.get_texture(face.textures[2]);
-        
-        let r0 = point::Point3D { x: (v0.x + 1.0) * ((width as f64)/2.0 - 1.0), y: (v0.y + 1.0) * ((height as f64)/2.0 - 1.0), z: v0.z }; 
-        let r1 = point::Point3D { x: (v1.x + 1.0) * ((width as f64)/2.0 - 1.0), y: (v1.y + 1.0) * ((height as f64)/2.0 - 1.0), z: v1.z };
-        let r2 = point::Point3D { x: (v2.x + 1.0) * ((width as f64)/2.0 - 1.0), y: (v2.y + 1.0) * ((height as f64)/2.0 - 1.0), z: v2.z };
+    let mut lighting = lighting::Lighting::new(
+        vec![
+            lighting::Light::directional(light_direction, vector::Vector3D { x: 1.0, y: 1.0, z: 1.0 }),
+        ],
+        vector::Vector3D { x: 0.1, y: 0.1, z: 0.1 });
 
-        let vector_ab = v1 - v0;
+    let specular_maps: Vec<Option<sampler::MipMap>> = (0..model.material_count())
+        .map(|i| model.get_material(i).specular_map.as_ref().map(load))
+        .collect();
 
-        let vector_ac = v2 - v0;
+    let no_material = material::Material::new("none");
+
+    // One mesh per material, faces without a material come first
+    let materials: Vec<Option<usize>> = std::iter::once(None).chain((0..model.material_count()).map(Some)).collect();
+
+    let meshes: Vec<mesh::Mesh> = materials.iter()
+        .map(|&material| {
+            let mut mesh = mesh::Mesh::from_obj_faces(
+                &model,
+                (0..model.face_count()).filter(|&i| model.get_face(i).material == material));
+            mesh.generate_tangents();
+            mesh
+        })
+        .collect();
+
+    // The depth pass from the light. The head fits in a unit sphere around the origin.
+    let mut shadow_map = shadow::ShadowMap::new(
+        1024,
+        1024,
+        shadow::directional_view_projection(&light_direction, &point::Point3D::default(), 1.0));
+
+    for mesh in meshes.iter() {
+        shadow_map.render(mesh, &model_matrix);
+    }
+
+    shadow_map.to_tga().write_tga_file("shadow.tga");
+    lighting.lights[0].shadow = Some(shadow_map.into());
+
+    for (&material, mesh) in materials.iter().zip(meshes.iter()) {
+        let shader = shaders::PhongShader {
+            uniforms: shaders::ShaderUniforms {
+                model: model_matrix,
+                view_projection: projection * view,
+                eye,
+                lighting: &lighting,
+                material: material.map_or(&no_material, |i| model.get_material(i)),
+                texture: material.and_then(|i| textures[i].as_ref()),
+                specular_map: material.and_then(|i| specular_maps[i].as_ref()),
+                normal_map: material.and_then(|i| normal_maps[i].as_ref()).map(shaders::NormalMap::TangentSpace),
+                sampler: sampler::Sampler::trilinear(sampler::WrapMode::Repeat),
+            }
+        };
+
+        drawing::draw_indexed(&shader, &shader, &mesh.vertices, &mesh.indices, &rasterizer, &mut framebuffer);
+    }
 
-        let normalized_cross_product = vector::cross_product(&vector_ab, &vector_ac).normalized();
+    if let Some(depth_buffer) = framebuffer.depth.as_ref() {
+        depth_buffer.to_tga().write_tga_file("depth.tga");
 
-        let intensity = normalized_cross_product.z * 1.0;
+        // Darken the creases the lights don't know about
+        let ambient_occlusion = ssao::AmbientOcclusion::new(16, 0.1);
+        let occlusion = ambient_occlusion.compute(depth_buffer, &projection, None);
 
-        if intensity > 0.0 {
-            drawing::triangle(r0, r1, r2, t0, t1, t2, &mut image, &mut zbuffer, &mut texture, &color::Color32::new((intensity*255.0) as u8, (intensity*255.0) as u8, (intensity*255.0) as u8, 255));
-        }
+        occlusion.to_color32().to_tga().write_tga_file("ao.tga");
+        ambient_occlusion.apply(&mut framebuffer.color, &occlusion);
     }
 
-    image.write_tga_file("model.tga");
-    texture.write_tga_file("texture.tga");
+    framebuffer.color.to_tga().write_tga_file("model.tga");
 }
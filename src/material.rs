@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::tokenizer;
+use crate::vector;
+use crate::wavefront::{self, ObjError, ObjErrorKind};
+
+/// A material from a Wavefront material library (.mtl) file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: String,
+    /// Ka
+    pub ambient: vector::Vector3D,
+    /// Kd
+    pub diffuse: vector::Vector3D,
+    /// Ks
+    pub specular: vector::Vector3D,
+    /// Ns, the specular exponent
+    pub shininess: f64,
+    /// d, or 1 - Tr. 1 is fully opaque.
+    pub opacity: f64,
+    /// illum, the illumination model. See the MTL spec for what the numbers mean.
+    pub illumination: u32,
+    /// map_Kd
+    pub diffuse_map: Option<PathBuf>,
+    /// map_Ks
+    pub specular_map: Option<PathBuf>,
+    /// map_Bump, bump or norm
+    pub normal_map: Option<PathBuf>,
+    /// map_d
+    pub alpha_map: Option<PathBuf>,
+}
+
+impl Material {
+    /// A material with nothing but a name. It's white, so a model that refers to a material we
+    /// don't know about still shows up.
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: vector::Vector3D::default(),
+            diffuse: vector::Vector3D::new(1.0, 1.0, 1.0),
+            specular: vector::Vector3D::default(),
+            shininess: 0.0,
+            opacity: 1.0,
+            illumination: 1,
+            diffuse_map: None,
+            specular_map: None,
+            normal_map: None,
+            alpha_map: None,
+        }
+    }
+
+    /// Make the texture paths relative to `directory` instead of the current directory.
+    /// Absolute paths are left alone.
+    pub fn resolve_paths(&mut self, directory: &Path) {
+        let mut maps = [&mut self.diffuse_map, &mut self.specular_map, &mut self.normal_map, &mut self.alpha_map];
+
+        for path in maps.iter_mut().filter_map(|map| map.as_mut()) {
+            *path = directory.join(&path);
+        }
+    }
+}
+
+/// Parse the contents of a Wavefront material library (.mtl) file.
+/// Statements we don't know about are skipped.
+pub fn parse(contents: &str) -> Result<Vec<Material>, ObjError> {
+    let mut materials: Vec<Material> = Vec::new();
+
+    for statement in tokenizer::Tokenizer::new(contents) {
+        let keyword = statement[0];
+        let arguments = &statement[1..];
+
+        if keyword.text == "newmtl" {
+            if arguments.len() != 1 {
+                return Err(ObjError::at(&keyword, ObjErrorKind::ArgumentCount { min: 1, max: 1, found: arguments.len() }));
+            }
+
+            materials.push(Material::new(arguments[0].text));
+            continue;
+        }
+
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            // Only comments may come before the first material
+            None => return Err(ObjError::at(&keyword, ObjErrorKind::NoMaterial)),
+        };
+
+        match keyword.text {
+            "Ka" => material.ambient = parse_color(&keyword, arguments)?,
+            "Kd" => material.diffuse = parse_color(&keyword, arguments)?,
+            "Ks" => material.specular = parse_color(&keyword, arguments)?,
+            "Ns" => material.shininess = wavefront::parse_floats(&keyword, arguments, 1, 1)?[0],
+            // d can have a -halo option in front of the value
+            "d" => material.opacity = wavefront::parse_floats(&keyword, last(&keyword, arguments)?, 1, 1)?[0],
+            "Tr" => material.opacity = 1.0 - wavefront::parse_floats(&keyword, arguments, 1, 1)?[0],
+            "illum" => {
+                let token = last(&keyword, arguments)?[0];
+
+                material.illumination = u32::from_str(token.text)
+                    .map_err(|_| ObjError::at(&token, ObjErrorKind::NotANumber))?;
+            },
+            "map_Kd" => material.diffuse_map = Some(parse_map(&keyword, arguments)?),
+            "map_Ks" => material.specular_map = Some(parse_map(&keyword, arguments)?),
+            "map_Bump" | "map_bump" | "bump" | "norm" => material.normal_map = Some(parse_map(&keyword, arguments)?),
+            "map_d" => material.alpha_map = Some(parse_map(&keyword, arguments)?),
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+// Colors are either r g b, or a single value for all three
+fn parse_color(keyword: &tokenizer::Token, arguments: &[tokenizer::Token]) -> Result<vector::Vector3D, ObjError> {
+    let values = wavefront::parse_floats(keyword, arguments, 1, 3)?;
+
+    match values[..] {
+        [gray] => Ok(vector::Vector3D::new(gray, gray, gray)),
+        [r, g, b] => Ok(vector::Vector3D::new(r, g, b)),
+        _ => Err(ObjError::at(keyword, ObjErrorKind::ArgumentCount { min: 1, max: 3, found: values.len() })),
+    }
+}
+
+// The last argument, which is where the value goes for statements that can have options
+fn last<'a, 'b>(keyword: &tokenizer::Token, arguments: &'b [tokenizer::Token<'a>]) -> Result<&'b [tokenizer::Token<'a>], ObjError> {
+    if arguments.is_empty() {
+        return Err(ObjError::at(keyword, ObjErrorKind::ArgumentCount { min: 1, max: 1, found: 0 }));
+    }
+
+    Ok(&arguments[arguments.len() - 1..])
+}
+
+// Texture maps can have options (`map_Bump -bm 0.5 normal.tga`), the file name comes last.
+// That means file names with spaces in them aren't supported.
+fn parse_map(keyword: &tokenizer::Token, arguments: &[tokenizer::Token]) -> Result<PathBuf, ObjError> {
+    Ok(PathBuf::from(last(keyword, arguments)?[0].text))
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::path::{Path, PathBuf};
+    use crate::material::{parse, Material};
+    use crate::vector::Vector3D;
+    use crate::wavefront::ObjErrorKind;
+
+    #[test]
+    fn test_parse_materials() {
+        let mtl = "# Two materials
+newmtl skin
+Ka 0.1 0.1 0.1
+Kd 0.8 0.6 0.5
+Ks 0.5
+Ns 32
+d 0.9
+illum 2
+map_Kd textures/skin.tga
+map_Ks skin_spec.tga
+map_Bump -bm 1.0 skin_normal.tga
+map_d skin_alpha.tga
+
+newmtl\teyes
+Tr 0.25
+norm eyes_normal.tga
+";
+        let materials = parse(mtl).unwrap();
+
+        assert_eq!(materials.len(), 2);
+
+        let skin = &materials[0];
+        assert_eq!(skin.name, "skin");
+        assert_eq!(skin.ambient, Vector3D::new(0.1, 0.1, 0.1));
+        assert_eq!(skin.diffuse, Vector3D::new(0.8, 0.6, 0.5));
+        assert_eq!(skin.specular, Vector3D::new(0.5, 0.5, 0.5));
+        assert_eq!(skin.shininess, 32.0);
+        assert_eq!(skin.opacity, 0.9);
+        assert_eq!(skin.illumination, 2);
+        assert_eq!(skin.diffuse_map, Some(PathBuf::from("textures/skin.tga")));
+        assert_eq!(skin.specular_map, Some(PathBuf::from("skin_spec.tga")));
+        assert_eq!(skin.normal_map, Some(PathBuf::from("skin_normal.tga")));
+        assert_eq!(skin.alpha_map, Some(PathBuf::from("skin_alpha.tga")));
+
+        let eyes = &materials[1];
+        assert_eq!(eyes.name, "eyes");
+        assert_eq!(eyes.opacity, 0.75);
+        assert_eq!(eyes.diffuse, Material::new("eyes").diffuse);
+        assert_eq!(eyes.normal_map, Some(PathBuf::from("eyes_normal.tga")));
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let mut material = Material::new("test");
+        material.diffuse_map = Some(PathBuf::from("textures/diffuse.tga"));
+        material.resolve_paths(Path::new("models/head"));
+
+        assert_eq!(material.diffuse_map, Some(PathBuf::from("models/head/textures/diffuse.tga")));
+        assert_eq!(material.normal_map, None);
+    }
+
+    #[test]
+    fn test_malformed_materials() {
+        let error = parse("Kd 1 1 1\n").err().unwrap();
+        assert!(matches!(error.kind, ObjErrorKind::NoMaterial));
+
+        let error = parse("newmtl a\nKd 1 1\n").err().unwrap();
+        assert_eq!((error.line, error.column), (2, 1));
+
+        let error = parse("newmtl a\nillum two\n").err().unwrap();
+        assert!(matches!(error.kind, ObjErrorKind::NotANumber));
+        assert_eq!(error.token, "two");
+
+        assert!(parse("newmtl a\nmap_Kd\n").is_err());
+    }
+}
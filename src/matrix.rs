@@ -0,0 +1,448 @@
+use std::ops::Mul;
+
+use crate::point;
+use crate::vector;
+
+// 3x3 matrices, stored row by row.
+// Mostly useful for transforming normals, which don't care about translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix3 {
+    pub rows: [[f64; 3]; 3]
+}
+
+// 4x4 matrices, stored row by row.
+// Vectors are treated as columns, so a transform is applied with `matrix * vector` and
+// `a * b` means "apply b, then a".
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub rows: [[f64; 4]; 4]
+}
+
+impl Matrix3 {
+    pub fn identity() -> Matrix3 {
+        Matrix3 {
+            rows: [
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0]]
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix3 {
+        let mut rows = [[0.0; 3]; 3];
+
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.rows[j][i];
+            }
+        }
+
+        Matrix3 { rows }
+    }
+
+    pub fn determinant(&self) -> f64 {
+        let m = &self.rows;
+
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // The inverse is the transpose of the matrix of cofactors divided by the determinant.
+    // Returns None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix3> {
+        let determinant = self.determinant();
+
+        if determinant.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let m = &self.rows;
+        let inverse_determinant = 1.0 / determinant;
+
+        // Rows and columns wrap around, which takes care of the alternating signs of the
+        // cofactors for us.
+        let mut rows = [[0.0; 3]; 3];
+
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                let (r0, r1) = ((j + 1) % 3, (j + 2) % 3);
+                let (c0, c1) = ((i + 1) % 3, (i + 2) % 3);
+
+                *value = (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) * inverse_determinant;
+            }
+        }
+
+        Some(Matrix3 { rows })
+    }
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    pub fn translate(x: f64, y: f64, z: f64) -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [1.0, 0.0, 0.0, x],
+                [0.0, 1.0, 0.0, y],
+                [0.0, 0.0, 1.0, z],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    pub fn scale(x: f64, y: f64, z: f64) -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // Counter-clockwise rotation of `angle` radians around the x axis
+    pub fn rotate_x(angle: f64) -> Matrix4 {
+        let (sin, cos) = angle.sin_cos();
+
+        Matrix4 {
+            rows: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, cos, -sin, 0.0],
+                [0.0, sin, cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // Counter-clockwise rotation of `angle` radians around the y axis
+    pub fn rotate_y(angle: f64) -> Matrix4 {
+        let (sin, cos) = angle.sin_cos();
+
+        Matrix4 {
+            rows: [
+                [cos, 0.0, sin, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [-sin, 0.0, cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // Counter-clockwise rotation of `angle` radians around the z axis
+    pub fn rotate_z(angle: f64) -> Matrix4 {
+        let (sin, cos) = angle.sin_cos();
+
+        Matrix4 {
+            rows: [
+                [cos, -sin, 0.0, 0.0],
+                [sin, cos, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // Counter-clockwise rotation of `angle` radians around an arbitrary axis (Rodrigues' formula)
+    pub fn rotate(axis: &vector::Vector3D, angle: f64) -> Matrix4 {
+        let a = axis.normalized();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+
+        Matrix4 {
+            rows: [
+                [t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.0],
+                [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.0],
+                [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.0],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // The view matrix of a camera sitting at `eye` and looking at `center`.
+    // It moves the world so that the camera ends up at the origin looking down the -z axis, with
+    // `up` pointing (roughly) along +y.
+    pub fn look_at(eye: &point::Point3D, center: &point::Point3D, up: &vector::Vector3D) -> Matrix4 {
+        let z = (*eye - *center).normalized();
+        let x = vector::cross_product(up, &z).normalized();
+        let y = vector::cross_product(&z, &x);
+
+        let eye = vector::Vector3D::from(*eye);
+
+        Matrix4 {
+            rows: [
+                [x.x, x.y, x.z, -x.dot(&eye)],
+                [y.x, y.y, y.z, -y.dot(&eye)],
+                [z.x, z.y, z.z, -z.dot(&eye)],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // Perspective projection, the same one OpenGL's gluPerspective builds.
+    // `fov_y` is the vertical field of view in radians. Everything between the `near` and `far`
+    // planes in front of the camera ends up with a z between -1 and 1 after the perspective
+    // divide.
+    pub fn perspective(fov_y: f64, aspect: f64, near: f64, far: f64) -> Matrix4 {
+        let f = 1.0 / (fov_y / 2.0).tan();
+
+        Matrix4 {
+            rows: [
+                [f / aspect, 0.0, 0.0, 0.0],
+                [0.0, f, 0.0, 0.0],
+                [0.0, 0.0, (far + near) / (near - far), 2.0 * far * near / (near - far)],
+                [0.0, 0.0, -1.0, 0.0]]
+        }
+    }
+
+    // Orthographic projection of the box [left, right] x [bottom, top] x [-near, -far] onto the
+    // [-1, 1] cube, the same one OpenGL's glOrtho builds.
+    pub fn orthographic(left: f64, right: f64, bottom: f64, top: f64, near: f64, far: f64) -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [2.0 / (right - left), 0.0, 0.0, -(right + left) / (right - left)],
+                [0.0, 2.0 / (top - bottom), 0.0, -(top + bottom) / (top - bottom)],
+                [0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near)],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    // Maps normalized device coordinates to window coordinates.
+    // x and y go from [-1, 1] to [x, x + width] and [y, y + height], z goes from [-1, 1] to [0, 1]
+    pub fn viewport(x: f64, y: f64, width: f64, height: f64) -> Matrix4 {
+        Matrix4 {
+            rows: [
+                [width / 2.0, 0.0, 0.0, x + width / 2.0],
+                [0.0, height / 2.0, 0.0, y + height / 2.0],
+                [0.0, 0.0, 0.5, 0.5],
+                [0.0, 0.0, 0.0, 1.0]]
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut rows = [[0.0; 4]; 4];
+
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.rows[j][i];
+            }
+        }
+
+        Matrix4 { rows }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting.
+    // We row-reduce the matrix to the identity while applying the same operations to an identity
+    // matrix. Whatever the identity turns into is the inverse.
+    // Returns None if the matrix is singular or has NaNs or infinities in it.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut m = self.rows;
+        let mut inverse = Matrix4::identity().rows;
+
+        for column in 0..4 {
+            // Pick the row with the largest value in this column to keep things numerically sane
+            let pivot = (column..4)
+                .max_by(|&a, &b| m[a][column].abs().total_cmp(&m[b][column].abs()))
+                .unwrap();
+
+            // NaN sorts above everything, so it ends up here if there's one
+            if !m[pivot][column].is_finite() || m[pivot][column].abs() < f64::EPSILON {
+                return None;
+            }
+
+            m.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let inverse_pivot = 1.0 / m[column][column];
+
+            for j in 0..4 {
+                m[column][j] *= inverse_pivot;
+                inverse[column][j] *= inverse_pivot;
+            }
+
+            for row in 0..4 {
+                if row != column {
+                    let factor = m[row][column];
+
+                    for j in 0..4 {
+                        m[row][j] -= factor * m[column][j];
+                        inverse[row][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+
+        Some(Matrix4 { rows: inverse })
+    }
+
+    // The upper-left 3x3 part of the matrix (i.e. without the translation)
+    pub fn to_matrix3(&self) -> Matrix3 {
+        let m = &self.rows;
+
+        Matrix3 {
+            rows: [
+                [m[0][0], m[0][1], m[0][2]],
+                [m[1][0], m[1][1], m[1][2]],
+                [m[2][0], m[2][1], m[2][2]]]
+        }
+    }
+
+    // The matrix to transform normals with when the model is transformed by this matrix.
+    // Normals need the inverse transpose so they stay perpendicular to their surface under
+    // non-uniform scaling.
+    pub fn normal_matrix(&self) -> Option<Matrix3> {
+        Some(self.to_matrix3().inverse()?.transpose())
+    }
+
+    // Transform a point, including the perspective divide
+    pub fn transform_point(&self, p: &point::Point3D) -> point::Point3D {
+        (*self * vector::Vector4::from(*p)).to_point()
+    }
+
+    // Transform a direction. Translation does not apply to directions.
+    pub fn transform_vector(&self, v: &vector::Vector3D) -> vector::Vector3D {
+        (*self * vector::Vector4::from(*v)).xyz()
+    }
+}
+
+impl Mul for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, other: Matrix3) -> Matrix3 {
+        let mut rows = [[0.0; 3]; 3];
+
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+
+        Matrix3 { rows }
+    }
+}
+
+impl Mul<vector::Vector3D> for Matrix3 {
+    type Output = vector::Vector3D;
+
+    fn mul(self, v: vector::Vector3D) -> vector::Vector3D {
+        let m = &self.rows;
+
+        vector::Vector3D {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z
+        }
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut rows = [[0.0; 4]; 4];
+
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.rows[i][k] * other.rows[k][j]).sum();
+            }
+        }
+
+        Matrix4 { rows }
+    }
+}
+
+// Multiplication with homogeneous coordinates
+impl Mul<vector::Vector4> for Matrix4 {
+    type Output = vector::Vector4;
+
+    fn mul(self, v: vector::Vector4) -> vector::Vector4 {
+        let mut result = vector::Vector4::default();
+
+        for i in 0..4 {
+            result[i] = (0..4).map(|k| self.rows[i][k] * v[k]).sum();
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::matrix::{Matrix3, Matrix4};
+    use crate::point::Point3D;
+    use crate::vector::Vector3D;
+
+    fn assert_close(a: &Matrix4, b: &Matrix4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a.rows[i][j] - b.rows[i][j]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inverse_undoes_transform() {
+        let m = Matrix4::translate(1.0, -2.0, 3.0)
+            * Matrix4::rotate(&Vector3D { x: 1.0, y: 1.0, z: 0.0 }, 0.7)
+            * Matrix4::scale(2.0, 3.0, 0.5);
+
+        assert_close(&(m * m.inverse().unwrap()), &Matrix4::identity());
+        assert_close(&(m.inverse().unwrap() * m), &Matrix4::identity());
+    }
+
+    #[test]
+    fn test_singular_matrix_has_no_inverse() {
+        assert!(Matrix4::scale(1.0, 0.0, 1.0).inverse().is_none());
+        assert!(Matrix3 { rows: [[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 1.0, 0.0]] }.inverse().is_none());
+    }
+
+    #[test]
+    fn test_nan_matrix_has_no_inverse() {
+        let mut m = Matrix4::identity();
+        m.rows[2][1] = f64::NAN;
+
+        assert!(m.inverse().is_none());
+    }
+
+    #[test]
+    fn test_matrix3_inverse() {
+        let m = Matrix3 { rows: [[2.0, 0.0, 1.0], [1.0, 3.0, 0.0], [0.0, 1.0, 4.0]] };
+
+        let product = m * m.inverse().unwrap();
+
+        for i in 0..3 {
+            for j in 0..3 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.rows[i][j] - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_look_at_moves_eye_to_origin() {
+        let eye = Point3D { x: 1.0, y: 2.0, z: 3.0 };
+        let center = Point3D { x: 0.0, y: 0.0, z: 0.0 };
+        let view = Matrix4::look_at(&eye, &center, &Vector3D { x: 0.0, y: 1.0, z: 0.0 });
+
+        let eye_in_view = view.transform_point(&eye);
+        assert!(eye_in_view.x.abs() < 1e-9 && eye_in_view.y.abs() < 1e-9 && eye_in_view.z.abs() < 1e-9);
+
+        // The center should be straight ahead, down the -z axis
+        let center_in_view = view.transform_point(&center);
+        assert!(center_in_view.x.abs() < 1e-9 && center_in_view.y.abs() < 1e-9);
+        assert!(center_in_view.z < 0.0);
+    }
+
+    #[test]
+    fn test_perspective_maps_near_and_far_planes() {
+        let projection = Matrix4::perspective(1.0, 1.0, 0.5, 10.0);
+
+        let near = projection.transform_point(&Point3D { x: 0.0, y: 0.0, z: -0.5 });
+        let far = projection.transform_point(&Point3D { x: 0.0, y: 0.0, z: -10.0 });
+
+        assert!((near.z + 1.0).abs() < 1e-9);
+        assert!((far.z - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use crate::point;
+use crate::vector;
+use crate::wavefront;
+
+/// One entry of a mesh's vertex buffer: everything the shaders get to know about a corner.
+/// Attributes the model doesn't have are zero.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Vertex {
+    pub position: point::Point3D,
+    pub uv: point::Point2D,
+    pub normal: vector::Vector3D,
+    /// xyz is the tangent, w is 1 or -1 depending on which way the bitangent points
+    pub tangent: vector::Vector4,
+}
+
+impl Vertex {
+    /// The third axis of tangent space, which together with the normal and the tangent makes up
+    /// the TBN basis
+    pub fn bitangent(&self) -> vector::Vector3D {
+        vector::cross_product(&self.normal, &self.tangent.xyz()) * self.tangent.w
+    }
+
+    // The bit patterns of all the attributes. Two corners are the same vertex if these match.
+    fn key(&self) -> [u64; 12] {
+        [
+            self.position.x, self.position.y, self.position.z,
+            self.uv.x, self.uv.y,
+            self.normal.x, self.normal.y, self.normal.z,
+            self.tangent.x, self.tangent.y, self.tangent.z, self.tangent.w,
+        ].map(f64::to_bits)
+    }
+}
+
+/// A triangle mesh the way a GPU wants it: a buffer of vertices, and a buffer of indices into
+/// it, three per triangle.
+///
+/// A vertex that's shared by several triangles is only stored once, so a vertex shader only
+/// has to run once for it (see `drawing::draw_indexed`).
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<usize>,
+}
+
+impl Mesh {
+    /// Build a mesh out of a list of triangles. Corners with exactly the same attributes get
+    /// welded into one vertex.
+    pub fn from_triangles<I: IntoIterator<Item = [Vertex; 3]>>(triangles: I) -> Mesh {
+        let mut mesh = Mesh::default();
+        let mut welded: HashMap<[u64; 12], usize> = HashMap::new();
+
+        for triangle in triangles {
+            for vertex in triangle.iter() {
+                let index = *welded.entry(vertex.key()).or_insert_with(|| {
+                    mesh.vertices.push(*vertex);
+                    mesh.vertices.len() - 1
+                });
+
+                mesh.indices.push(index);
+            }
+        }
+
+        mesh
+    }
+
+    /// All the faces of a Wavefront model
+    pub fn from_obj(model: &wavefront::WaveFrontFile) -> Mesh {
+        Mesh::from_obj_faces(model, 0..model.face_count())
+    }
+
+    /// Some of the faces of a Wavefront model, say the ones of a group (`Group::faces`) or the
+    /// ones with a certain material
+    pub fn from_obj_faces<I: IntoIterator<Item = usize>>(model: &wavefront::WaveFrontFile, faces: I) -> Mesh {
+        Mesh::from_triangles(faces.into_iter().map(|face| {
+            let vertex = |corner: usize| {
+                let corner = model.get_corner(face, corner);
+
+                Vertex {
+                    position: corner.position,
+                    uv: corner.texture.unwrap_or_default(),
+                    normal: corner.normal.unwrap_or_default(),
+                    tangent: vector::Vector4::default(),
+                }
+            };
+
+            [vertex(0), vertex(1), vertex(2)]
+        }))
+    }
+
+    /// Work out the tangents of all the vertices from their texture coordinates, the way
+    /// MikkTSpace does, so normal maps baked by other tools come out right:
+    ///
+    /// - Every triangle gets the tangent and bitangent that line up with the u and v directions
+    ///   of its texture coordinates
+    /// - At every corner the tangent is made perpendicular to the vertex normal and weighted by
+    ///   the angle of the corner
+    /// - The corners that share a vertex get averaged, but only if their texture coordinates
+    ///   are mirrored the same way. If they aren't the vertex gets split in two.
+    /// - Degenerate triangles (no area, or texture coordinates all on a line) don't have a
+    ///   tangent of their own. They take the ones of the triangles around them.
+    ///
+    /// The handedness ends up in `tangent.w`. Vertices that only belong to degenerate triangles
+    /// get some tangent that's perpendicular to the normal.
+    pub fn generate_tangents(&mut self) {
+        // Sum of the weighted tangents for every vertex and handedness, in the order they show up
+        let mut sums: Vec<((usize, bool), vector::Vector3D)> = Vec::new();
+        let mut slots: HashMap<(usize, bool), usize> = HashMap::new();
+        // Which slot every index goes to. Corners of degenerate triangles don't know yet.
+        let mut corner_slots: Vec<Option<usize>> = Vec::with_capacity(self.indices.len());
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [self.vertices[triangle[0]], self.vertices[triangle[1]], self.vertices[triangle[2]]];
+
+            let (edge1, edge2) = (b.position - a.position, c.position - a.position);
+            let (duv1, duv2) = (b.uv - a.uv, c.uv - a.uv);
+
+            let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+            let (tangent, bitangent) = if determinant != 0.0 {
+                ((edge1 * duv2.y - edge2 * duv1.y) * (1.0 / determinant),
+                 (edge2 * duv1.x - edge1 * duv2.x) * (1.0 / determinant))
+            } else {
+                (vector::Vector3D::default(), vector::Vector3D::default())
+            };
+
+            let corners = [a.position, b.position, c.position];
+
+            for (k, &index) in triangle.iter().enumerate() {
+                let normal = self.vertices[index].normal;
+
+                // Gram-Schmidt: take out the part that goes along the normal
+                let projected = tangent - normal * normal.dot(&tangent);
+                let length = projected.length();
+
+                if !(length > 0.0 && length.is_finite()) {
+                    corner_slots.push(None);
+                    continue;
+                }
+
+                let right_handed = vector::cross_product(&normal, &projected).dot(&bitangent) >= 0.0;
+
+                let slot = *slots.entry((index, right_handed)).or_insert_with(|| {
+                    sums.push(((index, right_handed), vector::Vector3D::default()));
+                    sums.len() - 1
+                });
+
+                let angle = angle_between(&(corners[(k + 1) % 3] - corners[k]), &(corners[(k + 2) % 3] - corners[k]));
+
+                sums[slot].1 = sums[slot].1 + projected * (angle / length);
+                corner_slots.push(Some(slot));
+            }
+        }
+
+        // Degenerate corners join whatever their vertex got from the other triangles, without
+        // splitting it. If there's nothing, they get a slot of their own.
+        let mut resolved = Vec::with_capacity(corner_slots.len());
+
+        for (slot, &index) in corner_slots.into_iter().zip(self.indices.iter()) {
+            let existing = slots.get(&(index, true)).into_iter().chain(slots.get(&(index, false))).min().copied();
+
+            let slot = slot.or(existing).unwrap_or_else(|| {
+                sums.push(((index, true), vector::Vector3D::default()));
+                slots.insert((index, true), sums.len() - 1);
+                sums.len() - 1
+            });
+
+            resolved.push(slot);
+        }
+
+        // The first handedness seen for a vertex keeps it, the other one gets a copy
+        let mut slot_vertices: Vec<usize> = Vec::with_capacity(sums.len());
+        let mut taken = vec![false; self.vertices.len()];
+
+        for &((index, right_handed), sum) in sums.iter() {
+            let mut vertex = self.vertices[index];
+
+            let tangent = if sum.length() > 0.0 { sum.normalized() } else { perpendicular(&vertex.normal) };
+            vertex.tangent = tangent.extend(if right_handed { 1.0 } else { -1.0 });
+
+            if taken[index] {
+                self.vertices.push(vertex);
+                slot_vertices.push(self.vertices.len() - 1);
+            } else {
+                self.vertices[index] = vertex;
+                taken[index] = true;
+                slot_vertices.push(index);
+            }
+        }
+
+        self.indices = resolved.iter().map(|&slot| slot_vertices[slot]).collect();
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// The three vertices of triangle `idx`
+    pub fn get_triangle(&self, idx: usize) -> [Vertex; 3] {
+        let indices = &self.indices[idx * 3..idx * 3 + 3];
+
+        [self.vertices[indices[0]], self.vertices[indices[1]], self.vertices[indices[2]]]
+    }
+}
+
+fn angle_between(a: &vector::Vector3D, b: &vector::Vector3D) -> f64 {
+    if a.length() == 0.0 || b.length() == 0.0 {
+        return 0.0;
+    }
+
+    (a.dot(b) / (a.length() * b.length())).clamp(-1.0, 1.0).acos()
+}
+
+// Some unit vector at a right angle to `normal`. Any will do.
+fn perpendicular(normal: &vector::Vector3D) -> vector::Vector3D {
+    let axis = if normal.x.abs() < 0.9 { vector::Vector3D::new(1.0, 0.0, 0.0) } else { vector::Vector3D::new(0.0, 1.0, 0.0) };
+    let tangent = axis - *normal * normal.dot(&axis);
+
+    if tangent.length() > 0.0 { tangent.normalized() } else { axis }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::mesh::{Mesh, Vertex};
+    use crate::point::{Point2D, Point3D};
+    use crate::vector::{Vector3D, Vector4};
+    use crate::wavefront::WaveFrontFile;
+
+    #[test]
+    fn test_shared_corners_are_welded() {
+        // A quad in two triangles, with the diagonal's corners written twice
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1
+f 1/1/1 3/3/1 4/4/1
+";
+        let mesh = Mesh::from_obj(&WaveFrontFile::parse(obj).unwrap());
+
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.get_triangle(1)[2].position, Point3D { x: 0.0, y: 1.0, z: 0.0 });
+    }
+
+    #[test]
+    fn test_seams_are_kept() {
+        // The same position with two different texture coordinates is two vertices. So are two
+        // corners with the same attributes but different indices in the file, only once.
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 1 1 0
+vt 0 0
+vt 0.5 0
+f 1/1 2/1 3/1
+f 1/2 2/1 4/1
+";
+        let mesh = Mesh::from_obj(&WaveFrontFile::parse(obj).unwrap());
+
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 1, 2]);
+    }
+
+    #[test]
+    fn test_faces_subset() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng a\nf 1 2 3\ng b\nf 1 3 4\n";
+        let model = WaveFrontFile::parse(obj).unwrap();
+
+        let b = Mesh::from_obj_faces(&model, model.groups()[1].faces.clone());
+
+        assert_eq!(b.vertex_count(), 3);
+        assert_eq!(b.vertices[2], Vertex { position: Point3D { x: 0.0, y: 1.0, z: 0.0 }, ..Vertex::default() });
+    }
+
+    fn vertex(x: f64, y: f64, u: f64, v: f64) -> Vertex {
+        Vertex {
+            position: Point3D { x, y, z: 0.0 },
+            uv: Point2D { x: u, y: v },
+            normal: Vector3D::new(0.0, 0.0, 1.0),
+            ..Vertex::default()
+        }
+    }
+
+    fn close(a: Vector4, b: Vector4) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn test_tangents_follow_texture_coordinates() {
+        // u goes along y and v goes against x, on a slanted triangle. The normal tilts a bit,
+        // so the tangent has to be bent to stay perpendicular to it.
+        let mut mesh = Mesh::from_triangles(vec![[vertex(0.0, 0.0, 0.0, 0.0), vertex(0.0, 1.0, 1.0, 0.0), vertex(-2.0, 0.5, 0.5, 1.0)]]);
+        mesh.vertices[0].normal = Vector3D::new(0.0, 0.6, 0.8);
+
+        mesh.generate_tangents();
+
+        assert!(close(mesh.vertices[1].tangent, Vector4::new(0.0, 1.0, 0.0, 1.0)));
+        assert!(close(mesh.vertices[0].tangent, Vector4::new(0.0, 0.8, -0.6, 1.0)));
+        assert!((mesh.vertices[1].bitangent() - Vector3D::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_mirrored_texture_coordinates_split_vertices() {
+        // Two triangles sharing the edge along x = 0. The one on the left has its texture
+        // mirrored, like the two halves of a face sharing one half of a texture.
+        let mut mesh = Mesh::from_triangles(vec![
+            [vertex(0.0, 0.0, 0.0, 0.0), vertex(1.0, 0.0, 1.0, 0.0), vertex(0.0, 1.0, 0.0, 1.0)],
+            [vertex(0.0, 0.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0, 1.0), vertex(-1.0, 0.0, 1.0, 0.0)],
+        ]);
+
+        assert_eq!(mesh.vertex_count(), 4);
+
+        mesh.generate_tangents();
+
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(mesh.triangle_count(), 2);
+
+        for vertex in mesh.get_triangle(0).iter() {
+            assert!(close(vertex.tangent, Vector4::new(1.0, 0.0, 0.0, 1.0)));
+        }
+
+        for vertex in mesh.get_triangle(1).iter() {
+            assert!(close(vertex.tangent, Vector4::new(-1.0, 0.0, 0.0, -1.0)));
+            // Both halves agree on which way v goes
+            assert!((vertex.bitangent() - Vector3D::new(0.0, 1.0, 0.0)).length() < 1e-9);
+        }
+
+        // Nothing moved around
+        assert_eq!(mesh.get_triangle(1)[2].position, Point3D { x: -1.0, y: 0.0, z: 0.0 });
+    }
+
+    #[test]
+    fn test_tangents_with_degenerate_texture_coordinates() {
+        // The second triangle has its texture coordinates all on a line and the third one
+        // nearly so, neither can say where u goes
+        let mut mesh = Mesh::from_triangles(vec![
+            [vertex(0.0, 0.0, 0.0, 0.0), vertex(1.0, 0.0, 1.0, 0.0), vertex(0.0, 1.0, 0.0, 1.0)],
+            [vertex(1.0, 0.0, 1.0, 0.0), vertex(1.0, 1.0, 2.0, 0.0), vertex(0.0, 1.0, 3.0, 0.0)],
+            [vertex(1.0, 1.0, 0.0, 0.0), vertex(2.0, 1.0, 0.0, 1e-160), vertex(2.0, 2.0, 1e-160, 1e-160)],
+        ]);
+
+        mesh.generate_tangents();
+
+        for vertex in mesh.vertices.iter() {
+            assert!(vertex.tangent.x.is_finite() && vertex.tangent.y.is_finite() && vertex.tangent.z.is_finite());
+            assert!((vertex.tangent.xyz().length() - 1.0).abs() < 1e-9);
+            assert!(vertex.tangent.xyz().dot(&vertex.normal).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_degenerate_triangles_borrow_tangents() {
+        // A mirrored triangle and one with its texture coordinates on a line, sharing an edge.
+        // The second one takes the tangents of the first instead of splitting the vertices.
+        let mut mesh = Mesh::from_triangles(vec![
+            [vertex(0.0, 0.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0, 1.0), vertex(-1.0, 0.0, 1.0, 0.0)],
+            [vertex(0.0, 0.0, 0.0, 0.0), vertex(1.0, 0.5, 0.0, 0.5), vertex(0.0, 1.0, 0.0, 1.0)],
+        ]);
+
+        assert_eq!(mesh.vertex_count(), 4);
+
+        mesh.generate_tangents();
+
+        assert_eq!(mesh.vertex_count(), 4);
+
+        let [a, b, c] = mesh.get_triangle(1);
+        assert!(close(a.tangent, Vector4::new(-1.0, 0.0, 0.0, -1.0)));
+        assert!(close(c.tangent, Vector4::new(-1.0, 0.0, 0.0, -1.0)));
+        // Only degenerate triangles around this one, so anything perpendicular goes
+        assert!(b.tangent.xyz().dot(&b.normal).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tangents_without_texture_coordinates() {
+        let mut mesh = Mesh::from_triangles(vec![[vertex(0.0, 0.0, 0.0, 0.0), vertex(1.0, 0.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0, 0.0)]]);
+
+        mesh.generate_tangents();
+
+        for vertex in mesh.vertices.iter() {
+            assert!((vertex.tangent.xyz().length() - 1.0).abs() < 1e-9);
+            assert!(vertex.tangent.xyz().dot(&vertex.normal).abs() < 1e-9);
+        }
+    }
+}
@@ -1,16 +1,16 @@
-use std::ops::Sub;
+use std::ops::{Add, Sub};
 
 use crate::vector;
 
 // 2-dimensional points
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
 pub struct Point2D { 
     pub x: f64, 
     pub y: f64 
 }
 
 // 3-dimensional points
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
 pub struct Point3D { 
     pub x: f64,
     pub y: f64, 
@@ -41,3 +41,43 @@ impl Sub for Point3D {
         }
     }
 }
+
+// Moving a point along a vector gives another point
+impl Add<vector::Vector2D> for Point2D {
+    type Output = Point2D;
+
+    fn add(self, v: vector::Vector2D) -> Point2D {
+        Point2D {
+            x: self.x + v.x,
+            y: self.y + v.y
+        }
+    }
+}
+
+impl Add<vector::Vector3D> for Point3D {
+    type Output = Point3D;
+
+    fn add(self, v: vector::Vector3D) -> Point3D {
+        Point3D {
+            x: self.x + v.x,
+            y: self.y + v.y,
+            z: self.z + v.z
+        }
+    }
+}
+
+impl Sub<vector::Vector2D> for Point2D {
+    type Output = Point2D;
+
+    fn sub(self, v: vector::Vector2D) -> Point2D {
+        self + v * -1.0
+    }
+}
+
+impl Sub<vector::Vector3D> for Point3D {
+    type Output = Point3D;
+
+    fn sub(self, v: vector::Vector3D) -> Point3D {
+        self + v * -1.0
+    }
+}
@@ -0,0 +1,394 @@
+use crate::color;
+use crate::image;
+use crate::point;
+use crate::tga;
+use crate::vector;
+
+/// Anything a sampler can read texels from.
+/// Texels are returned as floating point colors with channels in [0, 1] so they can be filtered.
+pub trait Texture {
+    fn texture_width(&self) -> usize;
+
+    fn texture_height(&self) -> usize;
+
+    /// The texel at (x, y). Both are guaranteed to be in bounds.
+    fn texel(&self, x: usize, y: usize) -> vector::Vector4;
+}
+
+impl Texture for tga::TGAFile {
+    fn texture_width(&self) -> usize {
+        self.get_width() as usize
+    }
+
+    fn texture_height(&self) -> usize {
+        self.get_height() as usize
+    }
+
+    fn texel(&self, x: usize, y: usize) -> vector::Vector4 {
+        self.get(x as u16, y as u16).to_vector()
+    }
+}
+
+impl Texture for image::Image<color::Color32> {
+    fn texture_width(&self) -> usize {
+        self.get_width()
+    }
+
+    fn texture_height(&self) -> usize {
+        self.get_height()
+    }
+
+    fn texel(&self, x: usize, y: usize) -> vector::Vector4 {
+        self.get(x, y).to_vector()
+    }
+}
+
+impl Texture for image::Image<vector::Vector4> {
+    fn texture_width(&self) -> usize {
+        self.get_width()
+    }
+
+    fn texture_height(&self) -> usize {
+        self.get_height()
+    }
+
+    fn texel(&self, x: usize, y: usize) -> vector::Vector4 {
+        self.get(x, y)
+    }
+}
+
+/// How to pick a color when a texture coordinate falls between texels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Take the texel the coordinate falls in
+    Nearest,
+    /// Blend the four closest texels depending on how close the coordinate is to each of them
+    Bilinear,
+}
+
+/// How to pick between the levels of a mip map
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MipmapMode {
+    /// Always read the full size texture
+    None,
+    /// Read the level closest to the computed level of detail
+    Nearest,
+    /// Read the two closest levels and blend between them. Combined with `Filter::Bilinear`,
+    /// this is trilinear filtering.
+    Linear,
+}
+
+/// What to do with texture coordinates outside of [0, 1]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WrapMode {
+    /// Tile the texture: 1.25 is the same as 0.25
+    Repeat,
+    /// Tile the texture, flipping every other tile: 1.25 is the same as 0.75
+    MirroredRepeat,
+    /// Stretch the texels on the edges: 1.25 is the same as 1.0
+    ClampToEdge,
+}
+
+impl WrapMode {
+    // Bring a texel index that might be out of bounds back into [0, size)
+    fn wrap(&self, index: i64, size: usize) -> usize {
+        let size = size as i64;
+
+        let wrapped = match self {
+            WrapMode::Repeat => index.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let index = index.rem_euclid(2 * size);
+                if index >= size { 2 * size - 1 - index } else { index }
+            },
+            WrapMode::ClampToEdge => index.clamp(0, size - 1),
+        };
+
+        wrapped as usize
+    }
+}
+
+/// Describes how a texture gets read: how texels are filtered and how coordinates outside of the
+/// texture are handled.
+///
+/// Texture coordinates go from (0, 0) at the bottom left of the texture to (1, 1) at the top
+/// right. The center of the texel at (x, y) sits at ((x + 0.5) / width, (y + 0.5) / height).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub mipmap: MipmapMode,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl Sampler {
+    /// A sampler using the same wrap mode in both directions and no mip mapping
+    pub fn new(filter: Filter, wrap: WrapMode) -> Sampler {
+        Sampler { filter, mipmap: MipmapMode::None, wrap_u: wrap, wrap_v: wrap }
+    }
+
+    /// Bilinear filtering within mip levels, blended linearly between levels
+    pub fn trilinear(wrap: WrapMode) -> Sampler {
+        Sampler { filter: Filter::Bilinear, mipmap: MipmapMode::Linear, wrap_u: wrap, wrap_v: wrap }
+    }
+
+    /// Read the texture at `uv`. The result has channels in [0, 1].
+    pub fn sample<T: Texture>(&self, texture: &T, uv: &point::Point2D) -> vector::Vector4 {
+        let width = texture.texture_width();
+        let height = texture.texture_height();
+
+        // Position in texel units
+        let x = uv.x * width as f64;
+        let y = uv.y * height as f64;
+
+        let texel = |x: i64, y: i64| texture.texel(self.wrap_u.wrap(x, width), self.wrap_v.wrap(y, height));
+
+        match self.filter {
+            Filter::Nearest => texel(x.floor() as i64, y.floor() as i64),
+            Filter::Bilinear => {
+                // Texel centers are at half-integers. Shifting by half a texel puts them at the
+                // integers, so floor() gives us the bottom-left texel of the four surrounding
+                // the coordinate and the fractional part tells us how far along we are.
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let bottom = texel(x0, y0).lerp(&texel(x0 + 1, y0), tx);
+                let top = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), tx);
+
+                bottom.lerp(&top, ty)
+            },
+        }
+    }
+
+    /// Same as `sample`, converted to 8 bits per channel
+    pub fn sample_color<T: Texture>(&self, texture: &T, uv: &point::Point2D) -> color::Color32 {
+        color::Color32::from_vector(&self.sample(texture, uv))
+    }
+
+    /// Read a mip map at `uv`, using the given level of detail.
+    /// Level 0 is the full size texture, level 1 is half the size, etc. Fractional levels sit in
+    /// between two levels.
+    pub fn sample_level(&self, mipmap: &MipMap, uv: &point::Point2D, lod: f64) -> vector::Vector4 {
+        let last = (mipmap.levels.len() - 1) as f64;
+        let lod = lod.clamp(0.0, last);
+
+        match self.mipmap {
+            MipmapMode::None => self.sample(&mipmap.levels[0], uv),
+            MipmapMode::Nearest => self.sample(&mipmap.levels[lod.round() as usize], uv),
+            MipmapMode::Linear => {
+                let level = lod.floor();
+                let t = lod - level;
+                let level = level as usize;
+
+                let finer = self.sample(&mipmap.levels[level], uv);
+
+                if t == 0.0 {
+                    finer
+                }
+                else {
+                    finer.lerp(&self.sample(&mipmap.levels[level + 1], uv), t)
+                }
+            },
+        }
+    }
+
+    /// Read a mip map at `uv`, picking the level of detail from how fast the texture coordinates
+    /// change between neighbouring pixels (see `FragmentInput::ddx` and `FragmentInput::ddy`).
+    ///
+    /// If moving one pixel on the screen moves us 4 texels in the texture, reading the full size
+    /// texture would skip 3 out of every 4 texels and we get aliasing. Reading level 2, where
+    /// each texel is the average of 4x4 texels of the full texture, gives the right result.
+    pub fn sample_grad(
+        &self,
+        mipmap: &MipMap,
+        uv: &point::Point2D,
+        ddx: &vector::Vector2D,
+        ddy: &vector::Vector2D) -> vector::Vector4
+    {
+        let size = vector::Vector2D { x: mipmap.get_width() as f64, y: mipmap.get_height() as f64 };
+
+        let texels_per_pixel = (*ddx * size).length().max((*ddy * size).length());
+
+        let lod = if texels_per_pixel > 0.0 { texels_per_pixel.log2() } else { 0.0 };
+
+        self.sample_level(mipmap, uv, lod)
+    }
+}
+
+/// A texture along with successively smaller copies of itself, each half the size of the
+/// previous one, down to 1x1.
+pub struct MipMap {
+    levels: Vec<image::Image<vector::Vector4>>,
+}
+
+impl MipMap {
+    /// Build the whole chain from a texture.
+    /// Each texel of a level is the average of the 2x2 texels it covers in the level above.
+    pub fn new<T: Texture>(texture: &T) -> MipMap {
+        let (width, height) = (texture.texture_width(), texture.texture_height());
+
+        let mut level = image::Image::new(width, height, vector::Vector4::default());
+
+        for y in 0..height {
+            for x in 0..width {
+                level.set(x, y, texture.texel(x, y)).unwrap();
+            }
+        }
+
+        let mut levels = vec![level];
+
+        while levels[levels.len() - 1].get_width() > 1 || levels[levels.len() - 1].get_height() > 1 {
+            let previous = &levels[levels.len() - 1];
+            let (width, height) = (previous.get_width(), previous.get_height());
+            let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+
+            let mut next = image::Image::new(next_width, next_height, vector::Vector4::default());
+
+            // When a side has an odd length, the last row/column of texels gets dropped.
+            // When it's already down to 1, the same texel gets used twice.
+            for y in 0..next_height {
+                for x in 0..next_width {
+                    let (x0, y0) = (2 * x, 2 * y);
+                    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+
+                    let average = (previous.get(x0, y0)
+                        + previous.get(x1, y0)
+                        + previous.get(x0, y1)
+                        + previous.get(x1, y1)) * 0.25;
+
+                    next.set(x, y, average).unwrap();
+                }
+            }
+
+            levels.push(next);
+        }
+
+        MipMap { levels }
+    }
+
+    /// Build the chain for a texture loaded with `TGAFile::from_bytes`
+    pub fn from_tga(file: &tga::TGAFile) -> MipMap {
+        MipMap::new(file)
+    }
+
+    /// Width of the full size texture
+    pub fn get_width(&self) -> usize {
+        self.levels[0].get_width()
+    }
+
+    /// Height of the full size texture
+    pub fn get_height(&self) -> usize {
+        self.levels[0].get_height()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn level(&self, index: usize) -> &image::Image<vector::Vector4> {
+        &self.levels[index]
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Sampler {
+        Sampler::new(Filter::Bilinear, WrapMode::Repeat)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::image::Image;
+    use crate::point::Point2D;
+    use crate::sampler::{Filter, MipMap, MipmapMode, Sampler, WrapMode};
+    use crate::vector::{Vector2D, Vector4};
+
+    // A 2x1 texture: black on the left, white on the right
+    fn texture() -> Image<Vector4> {
+        let mut image = Image::new(2, 1, Vector4::new(0.0, 0.0, 0.0, 1.0));
+        image.set(1, 0, Vector4::new(1.0, 1.0, 1.0, 1.0)).unwrap();
+        image
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        assert_eq!(WrapMode::Repeat.wrap(5, 4), 1);
+        assert_eq!(WrapMode::Repeat.wrap(-1, 4), 3);
+        assert_eq!(WrapMode::MirroredRepeat.wrap(4, 4), 3);
+        assert_eq!(WrapMode::MirroredRepeat.wrap(-1, 4), 0);
+        assert_eq!(WrapMode::MirroredRepeat.wrap(9, 4), 1);
+        assert_eq!(WrapMode::ClampToEdge.wrap(-3, 4), 0);
+        assert_eq!(WrapMode::ClampToEdge.wrap(7, 4), 3);
+    }
+
+    #[test]
+    fn test_nearest_does_not_go_out_of_bounds() {
+        let sampler = Sampler::new(Filter::Nearest, WrapMode::ClampToEdge);
+
+        assert_eq!(sampler.sample(&texture(), &Point2D { x: 1.0, y: 1.0 }).x, 1.0);
+        assert_eq!(sampler.sample(&texture(), &Point2D { x: -0.5, y: 0.5 }).x, 0.0);
+    }
+
+    #[test]
+    fn test_bilinear_blends_neighbours() {
+        let sampler = Sampler::new(Filter::Bilinear, WrapMode::ClampToEdge);
+
+        // Right between the two texel centers
+        assert!((sampler.sample(&texture(), &Point2D { x: 0.5, y: 0.5 }).x - 0.5).abs() < 1e-9);
+        // Right on the center of the white texel
+        assert!((sampler.sample(&texture(), &Point2D { x: 0.75, y: 0.5 }).x - 1.0).abs() < 1e-9);
+        // With repeat, the left edge blends with the right edge of the texture
+        let repeat = Sampler::new(Filter::Bilinear, WrapMode::Repeat);
+        assert!((repeat.sample(&texture(), &Point2D { x: 0.0, y: 0.5 }).x - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mipmap_levels() {
+        // 4x2 checkerboard
+        let mut image = Image::new(4, 2, Vector4::new(0.0, 0.0, 0.0, 1.0));
+        for y in 0..2 {
+            for x in 0..4 {
+                if (x + y) % 2 == 0 {
+                    image.set(x, y, Vector4::new(1.0, 1.0, 1.0, 1.0)).unwrap();
+                }
+            }
+        }
+
+        let mipmap = MipMap::new(&image);
+
+        assert_eq!(mipmap.level_count(), 3);
+        assert_eq!((mipmap.level(1).get_width(), mipmap.level(1).get_height()), (2, 1));
+        assert_eq!((mipmap.level(2).get_width(), mipmap.level(2).get_height()), (1, 1));
+        assert!((mipmap.level(1).get(0, 0).x - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sample_grad_picks_level_from_derivatives() {
+        let mut image = Image::new(8, 8, Vector4::new(0.0, 0.0, 0.0, 1.0));
+        for y in 0..8 {
+            for x in 0..8 {
+                if (x + y) % 2 == 0 {
+                    image.set(x, y, Vector4::new(1.0, 1.0, 1.0, 1.0)).unwrap();
+                }
+            }
+        }
+
+        let mipmap = MipMap::new(&image);
+        let uv = Point2D { x: 0.5 / 8.0, y: 0.5 / 8.0 };
+        let sampler = Sampler::trilinear(WrapMode::Repeat);
+
+        // One texel per pixel: level 0, we see the white texel
+        let one_texel = Vector2D::new(1.0 / 8.0, 0.0);
+        assert!((sampler.sample_grad(&mipmap, &uv, &one_texel, &one_texel).x - 1.0).abs() < 1e-9);
+
+        // Four texels per pixel: level 2, the checkerboard averages out to gray
+        let four_texels = Vector2D::new(4.0 / 8.0, 0.0);
+        assert!((sampler.sample_grad(&mipmap, &uv, &four_texels, &four_texels).x - 0.5).abs() < 1e-9);
+
+        // Without mip mapping, we're stuck at level 0
+        let mut no_mips = sampler;
+        no_mips.mipmap = MipmapMode::None;
+        assert!((no_mips.sample_grad(&mipmap, &uv, &four_texels, &four_texels).x - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::color;
+use crate::point;
+use crate::vector;
+
+/// Values produced by the vertex stage that get interpolated across a triangle
+/// before being handed to the fragment stage.
+///
+/// All the rasterizer needs to interpolate something is to be able to scale it and add it to
+/// another value of the same kind. Everything else (barycentric blending, lerping along an edge)
+/// is built on top of those two operations.
+pub trait Varying: Copy {
+    fn scale(&self, factor: f64) -> Self;
+
+    fn add(&self, other: &Self) -> Self;
+
+    /// Blend the values at the three corners of a triangle using barycentric weights
+    fn barycentric(values: &[Self; 3], weights: [f64; 3]) -> Self {
+        values[0].scale(weights[0])
+            .add(&values[1].scale(weights[1]))
+            .add(&values[2].scale(weights[2]))
+    }
+}
+
+/// What the vertex stage hands to the rasterizer for each corner of a triangle
+#[derive(Copy, Clone)]
+pub struct VertexOutput<V: Varying> {
+    /// Position in clip space: x, y, z, w. The rasterizer takes care of the perspective divide
+    /// and the viewport transform.
+    pub position: vector::Vector4,
+    pub varyings: V,
+}
+
+/// What the rasterizer hands to the fragment stage for each covered pixel
+pub struct FragmentInput<V: Varying> {
+    /// Window-space position of the fragment. x and y are in pixels, z is the depth in [0, 1]
+    pub frag_coord: point::Point3D,
+    pub varyings: V,
+    /// How much the varyings change when moving one pixel to the right
+    pub ddx: V,
+    /// How much the varyings change when moving one pixel up
+    pub ddy: V,
+}
+
+/// The result of running the fragment stage on a pixel.
+/// `T` is whatever the shader writes to the framebuffer's color attachments: a `Color32` most of
+/// the time, but it can be floating point color or a tuple with one value per attachment.
+pub enum Fragment<T = color::Color32> {
+    /// Write this color (and the fragment's depth) to the target
+    Color(T),
+    /// Leave the pixel untouched
+    Discard,
+}
+
+/// The vertex stage.
+/// Takes whatever per-vertex data the model provides and produces a clip-space position along
+/// with the varyings the fragment stage needs.
+pub trait VertexShader {
+    type Input;
+    type Varyings: Varying;
+
+    fn vertex(&self, input: &Self::Input) -> VertexOutput<Self::Varyings>;
+}
+
+/// The fragment stage.
+/// Runs once per covered pixel with the interpolated varyings. `O` is the type of the shader's
+/// output, see `Fragment`.
+pub trait FragmentShader<O = color::Color32> {
+    type Varyings: Varying;
+
+    fn fragment(&self, input: &FragmentInput<Self::Varyings>) -> Fragment<O>;
+}
+
+impl Varying for () {
+    fn scale(&self, _factor: f64) -> Self {}
+
+    fn add(&self, _other: &Self) -> Self {}
+}
+
+impl Varying for f64 {
+    fn scale(&self, factor: f64) -> Self {
+        self * factor
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        self + other
+    }
+}
+
+impl Varying for point::Point2D {
+    fn scale(&self, factor: f64) -> Self {
+        point::Point2D { x: self.x * factor, y: self.y * factor }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        point::Point2D { x: self.x + other.x, y: self.y + other.y }
+    }
+}
+
+impl Varying for point::Point3D {
+    fn scale(&self, factor: f64) -> Self {
+        point::Point3D { x: self.x * factor, y: self.y * factor, z: self.z * factor }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        point::Point3D { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+}
+
+macro_rules! impl_varying_for_vector {
+    ($name:ty) => {
+        impl Varying for $name {
+            fn scale(&self, factor: f64) -> Self {
+                *self * factor
+            }
+
+            fn add(&self, other: &Self) -> Self {
+                *self + *other
+            }
+        }
+    };
+}
+
+impl_varying_for_vector!(vector::Vector2D);
+impl_varying_for_vector!(vector::Vector3D);
+impl_varying_for_vector!(vector::Vector4);
+
+// Shaders usually need to pass more than one thing along, so tuples of varyings are varyings
+// too.
+macro_rules! impl_varying_for_tuple {
+    ($($name:ident : $idx:tt),+) => {
+        impl<$($name: Varying),+> Varying for ($($name,)+) {
+            fn scale(&self, factor: f64) -> Self {
+                ($(self.$idx.scale(factor),)+)
+            }
+
+            fn add(&self, other: &Self) -> Self {
+                ($(self.$idx.add(&other.$idx),)+)
+            }
+        }
+    };
+}
+
+impl_varying_for_tuple!(A: 0);
+impl_varying_for_tuple!(A: 0, B: 1);
+impl_varying_for_tuple!(A: 0, B: 1, C: 2);
+impl_varying_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_varying_for_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
@@ -0,0 +1,377 @@
+use crate::color;
+use crate::lighting;
+use crate::material;
+use crate::matrix;
+use crate::mesh;
+use crate::point;
+use crate::sampler;
+use crate::shader::{Fragment, FragmentInput, FragmentShader, VertexOutput, VertexShader};
+use crate::vector;
+
+// Ready-made shaders for models loaded from Wavefront files.
+// Both light the model with the lights in `lighting::Lighting` and the colors of its material.
+// They differ in where the lighting gets computed. Vertices without a normal (a zero normal)
+// are drawn unlit, in their diffuse color.
+
+/// A texture that replaces the interpolated normal. The normals are stored as colors: every
+/// channel maps [0, 1] to [-1, 1].
+#[derive(Copy, Clone)]
+pub enum NormalMap<'a> {
+    /// The usual bluish kind: x goes along the tangent, y along the bitangent and z along the
+    /// normal, so flat is (0.5, 0.5, 1). Needs tangents (see `mesh::Mesh::generate_tangents`).
+    TangentSpace(&'a sampler::MipMap),
+    /// The normals are in model space, and don't care about the mesh's normals or tangents
+    ObjectSpace(&'a sampler::MipMap),
+}
+
+/// What the shaders need to know about the scene
+#[derive(Copy, Clone)]
+pub struct ShaderUniforms<'a> {
+    /// Model to world
+    pub model: matrix::Matrix4,
+    /// World to clip space: projection * view
+    pub view_projection: matrix::Matrix4,
+    /// Where the camera is, in world space. Highlights depend on it.
+    pub eye: point::Point3D,
+    pub lighting: &'a lighting::Lighting,
+    /// Ka, Kd, Ks and Ns. Texture maps come in separately.
+    pub material: &'a material::Material,
+    /// The diffuse texture. It tints both the ambient and the diffuse color.
+    pub texture: Option<&'a sampler::MipMap>,
+    /// Tints the specular color
+    pub specular_map: Option<&'a sampler::MipMap>,
+    /// Only used by `PhongShader`, Gouraud shading has nothing to put it on
+    pub normal_map: Option<NormalMap<'a>>,
+    /// Used for all the textures
+    pub sampler: sampler::Sampler,
+}
+
+impl<'a> ShaderUniforms<'a> {
+    // Transform a normal from model space to world space
+    fn world_normal(&self, normal: &vector::Vector3D) -> vector::Vector3D {
+        let normal_matrix = self.model.normal_matrix().unwrap_or_else(matrix::Matrix3::identity);
+
+        (normal_matrix * *normal).normalized()
+    }
+
+    // Tangents lie on the surface, so unlike normals they transform just like the model does
+    fn world_tangent(&self, tangent: &vector::Vector4) -> vector::Vector4 {
+        self.model.transform_vector(&tangent.xyz()).extend(tangent.w)
+    }
+
+    // The normal to light the fragment with, in world space. `normal` and `tangent` are the
+    // interpolated world space ones.
+    fn shading_normal(
+        &self,
+        normal: &vector::Vector3D,
+        tangent: &vector::Vector4,
+        uv: &point::Point2D,
+        ddx: &point::Point2D,
+        ddy: &point::Point2D) -> vector::Vector3D
+    {
+        // Interpolating unit vectors doesn't give unit vectors
+        let normal = normal.normalized();
+
+        let sample = |map: &sampler::MipMap| {
+            let color = self.sampler.sample_grad(map, uv, &(*ddx).into(), &(*ddy).into());
+
+            color.xyz() * 2.0 - vector::Vector3D::new(1.0, 1.0, 1.0)
+        };
+
+        match self.normal_map {
+            None => normal,
+            Some(NormalMap::ObjectSpace(map)) => self.world_normal(&sample(map)),
+            Some(NormalMap::TangentSpace(map)) => {
+                // Gram-Schmidt again, interpolation bends the tangent away from the normal
+                let tangent_direction = tangent.xyz() - normal * normal.dot(&tangent.xyz());
+
+                // Without a tangent we can't tell which way the map goes
+                if tangent_direction.length() == 0.0 {
+                    return normal;
+                }
+
+                let t = tangent_direction.normalized();
+                let b = vector::cross_product(&normal, &t) * tangent.w.signum();
+                let n = sample(map);
+
+                (t * n.x + b * n.y + normal * n.z).normalized()
+            }
+        }
+    }
+
+    fn clip_position(&self, position: &point::Point3D) -> vector::Vector4 {
+        self.view_projection * (self.model * vector::Vector4::from(*position))
+    }
+
+    fn world_position(&self, position: &point::Point3D) -> point::Point3D {
+        (self.model * vector::Vector4::from(*position)).to_point()
+    }
+
+    fn sample(
+        &self,
+        map: Option<&sampler::MipMap>,
+        uv: &point::Point2D,
+        ddx: &point::Point2D,
+        ddy: &point::Point2D) -> vector::Vector4
+    {
+        match map {
+            Some(map) => self.sampler.sample_grad(map, uv, &(*ddx).into(), &(*ddy).into()),
+            None => vector::Vector4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+
+    fn illuminate(&self, position: &point::Point3D, normal: &vector::Vector3D) -> lighting::Illumination {
+        self.lighting.illuminate(position, normal, &self.eye, self.material.shininess)
+    }
+
+    // Combine the light with the colors of the material at `uv`
+    fn shade(
+        &self,
+        light: &lighting::Illumination,
+        uv: &point::Point2D,
+        ddx: &point::Point2D,
+        ddy: &point::Point2D) -> color::Color32
+    {
+        let texture = self.sample(self.texture, uv, ddx, ddy);
+        let specular = self.sample(self.specular_map, uv, ddx, ddy).xyz();
+
+        let color = light.color(
+            &(self.material.ambient * texture.xyz()),
+            &(self.material.diffuse * texture.xyz()),
+            &(self.material.specular * specular));
+
+        color::Color32::from_vector(&color.extend(texture.w * self.material.opacity))
+    }
+}
+
+// What unlit vertices get: just the diffuse color
+fn unlit() -> lighting::Illumination {
+    lighting::Illumination { diffuse: vector::Vector3D::new(1.0, 1.0, 1.0), ..lighting::Illumination::default() }
+}
+
+/// Lighting computed once per vertex and interpolated across the triangle.
+/// Cheap, but highlights and shadows between vertices get smeared.
+pub struct GouraudShader<'a> {
+    pub uniforms: ShaderUniforms<'a>,
+}
+
+impl<'a> VertexShader for GouraudShader<'a> {
+    type Input = mesh::Vertex;
+    // Texture coordinate, and the diffuse and specular light that reaches the vertex
+    type Varyings = (point::Point2D, vector::Vector3D, vector::Vector3D);
+
+    fn vertex(&self, input: &mesh::Vertex) -> VertexOutput<Self::Varyings> {
+        let light = if input.normal == vector::Vector3D::default() {
+            unlit()
+        } else {
+            self.uniforms.illuminate(&self.uniforms.world_position(&input.position), &self.uniforms.world_normal(&input.normal))
+        };
+
+        VertexOutput {
+            position: self.uniforms.clip_position(&input.position),
+            varyings: (input.uv, light.diffuse, light.specular),
+        }
+    }
+}
+
+impl<'a> FragmentShader for GouraudShader<'a> {
+    type Varyings = (point::Point2D, vector::Vector3D, vector::Vector3D);
+
+    fn fragment(&self, input: &FragmentInput<Self::Varyings>) -> Fragment {
+        let (uv, diffuse, specular) = input.varyings;
+        let light = lighting::Illumination { ambient: self.uniforms.lighting.ambient, diffuse, specular };
+
+        Fragment::Color(self.uniforms.shade(&light, &uv, &input.ddx.0, &input.ddy.0))
+    }
+}
+
+/// The normal gets interpolated across the triangle and lighting is computed for every pixel.
+pub struct PhongShader<'a> {
+    pub uniforms: ShaderUniforms<'a>,
+}
+
+impl<'a> VertexShader for PhongShader<'a> {
+    type Input = mesh::Vertex;
+    // Texture coordinate, world space normal, world space tangent and world space position
+    type Varyings = (point::Point2D, vector::Vector3D, vector::Vector4, point::Point3D);
+
+    fn vertex(&self, input: &mesh::Vertex) -> VertexOutput<Self::Varyings> {
+        VertexOutput {
+            position: self.uniforms.clip_position(&input.position),
+            varyings: (
+                input.uv,
+                // A zero normal has no direction, and it has to stay zero to mark the vertex unlit
+                if input.normal == vector::Vector3D::default() {
+                    input.normal
+                } else {
+                    self.uniforms.world_normal(&input.normal)
+                },
+                self.uniforms.world_tangent(&input.tangent),
+                self.uniforms.world_position(&input.position)
+            ),
+        }
+    }
+}
+
+impl<'a> FragmentShader for PhongShader<'a> {
+    type Varyings = (point::Point2D, vector::Vector3D, vector::Vector4, point::Point3D);
+
+    fn fragment(&self, input: &FragmentInput<Self::Varyings>) -> Fragment {
+        let (uv, normal, tangent, position) = input.varyings;
+
+        let light = if normal == vector::Vector3D::default() {
+            unlit()
+        } else {
+            self.uniforms.illuminate(&position, &self.uniforms.shading_normal(&normal, &tangent, &uv, &input.ddx.0, &input.ddy.0))
+        };
+
+        Fragment::Color(self.uniforms.shade(&light, &uv, &input.ddx.0, &input.ddy.0))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::color::Color32;
+    use crate::drawing;
+    use crate::framebuffer::Framebuffer;
+    use crate::image::Image;
+    use crate::lighting::{Light, Lighting};
+    use crate::material::Material;
+    use crate::matrix::Matrix4;
+    use crate::mesh::Vertex;
+    use crate::point::{Point2D, Point3D};
+    use crate::sampler::{MipMap, Sampler};
+    use crate::shader::{Fragment, FragmentInput, FragmentShader};
+    use crate::shaders::{GouraudShader, NormalMap, PhongShader, ShaderUniforms};
+    use crate::vector::{Vector3D, Vector4};
+
+    // A normal map that's the same everywhere
+    fn normal_map(normal: Vector3D) -> MipMap {
+        let color = (normal + Vector3D::new(1.0, 1.0, 1.0)) * 0.5;
+
+        MipMap::new(&Image::new(4, 4, color.extend(1.0)))
+    }
+
+    // How bright a white fragment facing +z with the given tangent comes out, lit by a single
+    // white directional light
+    fn brightness(light_direction: Vector3D, normal_map: Option<NormalMap>, model: Matrix4, tangent: Vector4) -> Color32 {
+        let lighting = Lighting::new(vec![Light::directional(light_direction, Vector3D::new(1.0, 1.0, 1.0))], Vector3D::default());
+        let material = Material::new("white");
+
+        let shader = PhongShader {
+            uniforms: ShaderUniforms {
+                model,
+                view_projection: Matrix4::identity(),
+                eye: Point3D { x: 0.0, y: 0.0, z: 5.0 },
+                lighting: &lighting,
+                material: &material,
+                texture: None,
+                specular_map: None,
+                normal_map,
+                sampler: Sampler::default(),
+            }
+        };
+
+        let input = FragmentInput {
+            frag_coord: Point3D::default(),
+            varyings: (Point2D { x: 0.5, y: 0.5 }, Vector3D::new(0.0, 0.0, 1.0), tangent, Point3D::default()),
+            ddx: Default::default(),
+            ddy: Default::default(),
+        };
+
+        match shader.fragment(&input) {
+            Fragment::Color(color) => color,
+            Fragment::Discard => panic!("Fragment was discarded"),
+        }
+    }
+
+    fn gray(value: f64) -> Color32 {
+        Color32::from_vector(&Vector4::new(value, value, value, 1.0))
+    }
+
+    const TANGENT: Vector4 = Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 };
+
+    #[test]
+    fn test_flat_normal_map_changes_nothing() {
+        let map = normal_map(Vector3D::new(0.0, 0.0, 1.0));
+        let light = Vector3D::new(-1.0, 0.0, -1.0);
+
+        let plain = brightness(light, None, Matrix4::identity(), TANGENT);
+        let mapped = brightness(light, Some(NormalMap::TangentSpace(&map)), Matrix4::identity(), TANGENT);
+
+        assert_eq!(plain, gray(0.5_f64.sqrt()));
+        assert_eq!(mapped, plain);
+    }
+
+    #[test]
+    fn test_tangent_space_normal_map() {
+        let identity = Matrix4::identity();
+
+        // Normals that point along the tangent
+        let along_tangent = Some(NormalMap::TangentSpace(&normal_map(Vector3D::new(1.0, 0.0, 0.0))));
+        let light = Vector3D::new(-1.0, 0.0, 0.0);
+
+        assert_eq!(brightness(light, along_tangent, identity, TANGENT), gray(1.0));
+        assert_eq!(brightness(light, along_tangent, identity, Vector4::new(-1.0, 0.0, 0.0, 1.0)), gray(0.0));
+
+        // Normals that point along the bitangent, which flips with the handedness
+        let along_bitangent = Some(NormalMap::TangentSpace(&normal_map(Vector3D::new(0.0, 1.0, 0.0))));
+        let light = Vector3D::new(0.0, -1.0, 0.0);
+
+        assert_eq!(brightness(light, along_bitangent, identity, TANGENT), gray(1.0));
+        assert_eq!(brightness(light, along_bitangent, identity, Vector4::new(1.0, 0.0, 0.0, -1.0)), gray(0.0));
+
+        // Without a tangent the map can't be used
+        assert_eq!(brightness(light, along_bitangent, identity, Vector4::default()), gray(0.0));
+    }
+
+    #[test]
+    fn test_object_space_normal_map() {
+        // The map says +x in model space, and the model is turned so that's +y in the world
+        let map = Some(NormalMap::ObjectSpace(&normal_map(Vector3D::new(1.0, 0.0, 0.0))));
+        let model = Matrix4::rotate_z(90.0_f64.to_radians());
+        let light = Vector3D::new(0.0, -1.0, 0.0);
+
+        // The tangent doesn't matter
+        assert_eq!(brightness(light, map, model, Vector4::default()), gray(1.0));
+        assert_eq!(brightness(light, map, model, Vector4::new(0.0, 1.0, 0.0, -1.0)), gray(1.0));
+    }
+
+    #[test]
+    fn test_gouraud_interpolates_lighting() {
+        let lighting = Lighting::new(vec![Light::directional(Vector3D::new(0.0, 0.0, -1.0), Vector3D::new(1.0, 1.0, 1.0))], Vector3D::default());
+        let material = Material::new("white");
+
+        let shader = GouraudShader {
+            uniforms: ShaderUniforms {
+                model: Matrix4::identity(),
+                view_projection: Matrix4::identity(),
+                eye: Point3D { x: 0.0, y: 0.0, z: 5.0 },
+                lighting: &lighting,
+                material: &material,
+                texture: None,
+                specular_map: None,
+                normal_map: None,
+                sampler: Sampler::default(),
+            }
+        };
+
+        // The corners on the left face the light, the one on the right is turned away from it
+        let corner = |x: f64, y: f64, normal: Vector3D| Vertex { position: Point3D { x, y, z: 0.0 }, normal, ..Vertex::default() };
+        let facing = Vector3D::new(0.0, 0.0, 1.0);
+        let triangle = [corner(-1.0, -1.0, facing), corner(1.0, 0.0, Vector3D::new(1.0, 0.0, 0.0)), corner(-1.0, 1.0, facing)];
+
+        let mut framebuffer = Framebuffer::rgba(64, 64);
+        drawing::draw_triangles(&shader, &shader, vec![triangle], &drawing::RasterizerState::default(), &mut framebuffer);
+
+        let row: Vec<Color32> = (0..60).map(|x| framebuffer.color.get(x, 32)).collect();
+
+        assert!(row[0].to_vector().x > 0.98, "{:?}", row[0]);
+        assert!(row[59].to_vector().x < 0.1, "{:?}", row[59]);
+
+        // The light fades out evenly from one side to the other
+        let halfway = row[30].to_vector().x;
+        assert!((halfway - 0.5).abs() < 0.05, "{}", halfway);
+        assert!(row.windows(2).all(|pair| pair[0].to_vector().x >= pair[1].to_vector().x));
+    }
+}
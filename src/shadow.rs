@@ -0,0 +1,453 @@
+use std::mem;
+use crate::depth;
+use crate::drawing;
+use crate::framebuffer;
+use crate::matrix;
+use crate::mesh;
+use crate::point;
+use crate::shader::{Fragment, FragmentInput, FragmentShader, VertexOutput, VertexShader};
+use crate::tga;
+use crate::vector;
+
+/// What a light can see, as a depth buffer rendered from its point of view.
+///
+/// Shadow mapping takes two passes. First the scene gets drawn from the light into the shadow
+/// map (`render`), keeping nothing but depth. Then while drawing the scene from the camera,
+/// every fragment gets projected into the shadow map: if something closer to the light was
+/// drawn there, the fragment is in shadow (`visibility`).
+///
+/// A shadow map covers what one projection sees. That's all of a directional or spot light.
+/// Point lights shine in every direction and need a `CubeShadowMap`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShadowMap {
+    depth: depth::DepthBuffer,
+    /// World space to the light's clip space. See `directional_view_projection` and
+    /// `spot_view_projection`.
+    pub view_projection: matrix::Matrix4,
+    /// How much further from the light than the shadow map says a fragment has to be before it
+    /// counts as shadowed. Without it surfaces shadow themselves in stripes ("shadow acne")
+    /// because the shadow map only has one depth per texel.
+    pub bias: f64,
+    /// Extra bias for surfaces the light hits at a grazing angle, where one texel of the
+    /// shadow map covers a long stretch of depth
+    pub slope_bias: f64,
+    /// Percentage closer filtering: average the shadow test over the texels up to this far
+    /// away, which softens the edges of shadows. 0 only tests the texel the fragment lands on.
+    pub pcf_radius: usize,
+    /// Which triangles get drawn into the shadow map. Culling front faces instead of back faces
+    /// moves the depths to the back of closed meshes, away from the lit surfaces, which is
+    /// another way to get rid of shadow acne.
+    pub rasterizer: drawing::RasterizerState,
+}
+
+impl ShadowMap {
+    pub fn new(width: usize, height: usize, view_projection: matrix::Matrix4) -> ShadowMap {
+        ShadowMap {
+            depth: depth::DepthBuffer::new(width, height),
+            view_projection,
+            bias: 0.002,
+            slope_bias: 0.01,
+            pcf_radius: 1,
+            rasterizer: drawing::RasterizerState::default(),
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.depth.get_width()
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.depth.get_height()
+    }
+
+    pub fn depth(&self) -> &depth::DepthBuffer {
+        &self.depth
+    }
+
+    /// Forget everything that was rendered
+    pub fn clear(&mut self) {
+        self.depth.clear();
+    }
+
+    /// The depth pass: draw a mesh that casts shadows. `model` takes it to world space.
+    pub fn render(&mut self, mesh: &mesh::Mesh, model: &matrix::Matrix4) {
+        let shader = DepthShader { model_view_projection: self.view_projection * *model };
+
+        let placeholder = depth::DepthBuffer::new(0, 0);
+        let mut framebuffer = framebuffer::Framebuffer::from_depth(mem::replace(&mut self.depth, placeholder));
+
+        drawing::draw_indexed(&shader, &shader, &mesh.vertices, &mesh.indices, &self.rasterizer, &mut framebuffer);
+
+        if let Some(depth) = framebuffer.depth {
+            self.depth = depth;
+        }
+    }
+
+    /// How much of the light reaches `position` (in world space): 1 if it's fully lit, 0 if
+    /// it's in shadow, and something in between at the soft edge of a shadow.
+    /// `lambert` is the cosine of the angle the light hits the surface at.
+    /// Anything outside of what the light sees is lit.
+    pub fn visibility(&self, position: &point::Point3D, lambert: f64) -> f64 {
+        let clip = self.view_projection * vector::Vector4::from(*position);
+
+        if clip.w <= 0.0 {
+            return 1.0;
+        }
+
+        // The same viewport transform the rasterizer does
+        let (width, height) = (self.get_width(), self.get_height());
+        let window = (matrix::Matrix4::viewport(0.0, 0.0, width as f64, height as f64) * clip).to_point();
+
+        if window.z > 1.0 {
+            return 1.0;
+        }
+
+        let bias = self.bias + self.slope_bias * (1.0 - lambert.clamp(0.0, 1.0));
+        // Texel (x, y) covers everything from x to x + 1, its center being at x + 0.5
+        let (x, y) = (window.x.floor() as i64, window.y.floor() as i64);
+        let radius = self.pcf_radius as i64;
+
+        let mut lit = 0;
+
+        for ty in y - radius..=y + radius {
+            for tx in x - radius..=x + radius {
+                let inside = tx >= 0 && ty >= 0 && (tx as usize) < width && (ty as usize) < height;
+
+                if !inside || window.z - bias <= self.depth.get(tx as usize, ty as usize) {
+                    lit += 1;
+                }
+            }
+        }
+
+        lit as f64 / ((2 * radius + 1) * (2 * radius + 1)) as f64
+    }
+
+    /// Picture of the shadow map for debugging, closest to the light is white
+    pub fn to_tga(&self) -> tga::TGAFile {
+        self.depth.to_tga()
+    }
+}
+
+/// Six shadow maps around a point light, one for every side of a cube, so it sees in every
+/// direction
+#[derive(Clone, Debug, PartialEq)]
+pub struct CubeShadowMap {
+    position: point::Point3D,
+    // Looking along +x, -x, +y, -y, +z and -z
+    faces: Vec<ShadowMap>,
+}
+
+impl CubeShadowMap {
+    /// `size` by `size` texels for every face. Only what's between `near` and `far` from the
+    /// light casts shadows.
+    pub fn new(size: usize, position: point::Point3D, near: f64, far: f64) -> CubeShadowMap {
+        // Percentage closer filtering looks at texels around the one a point lands on. Near the
+        // edge of a face some of those would be off the map, so the faces see a bit more than
+        // a quarter turn and overlap with each other.
+        let margin = 4.0;
+        let half_angle = (size as f64 / (size as f64 - margin).max(1.0)).atan();
+
+        let face = |direction: vector::Vector3D| {
+            let view = matrix::Matrix4::look_at(&position, &(position + direction), &up_for(&direction));
+            let projection = matrix::Matrix4::perspective(2.0 * half_angle, 1.0, near, far);
+
+            ShadowMap::new(size, size, projection * view)
+        };
+
+        CubeShadowMap {
+            position,
+            faces: vec![
+                face(vector::Vector3D::new(1.0, 0.0, 0.0)),
+                face(vector::Vector3D::new(-1.0, 0.0, 0.0)),
+                face(vector::Vector3D::new(0.0, 1.0, 0.0)),
+                face(vector::Vector3D::new(0.0, -1.0, 0.0)),
+                face(vector::Vector3D::new(0.0, 0.0, 1.0)),
+                face(vector::Vector3D::new(0.0, 0.0, -1.0)),
+            ],
+        }
+    }
+
+    /// Where the light is
+    pub fn position(&self) -> point::Point3D {
+        self.position
+    }
+
+    /// The faces looking along +x, -x, +y, -y, +z and -z
+    pub fn faces(&self) -> &[ShadowMap] {
+        &self.faces
+    }
+
+    /// For setting `bias` and friends on each of the faces
+    pub fn faces_mut(&mut self) -> &mut [ShadowMap] {
+        &mut self.faces
+    }
+
+    /// Forget everything that was rendered
+    pub fn clear(&mut self) {
+        for face in self.faces.iter_mut() {
+            face.clear();
+        }
+    }
+
+    /// The depth pass: draw a mesh that casts shadows into all six faces. `model` takes it to
+    /// world space.
+    pub fn render(&mut self, mesh: &mesh::Mesh, model: &matrix::Matrix4) {
+        for face in self.faces.iter_mut() {
+            face.render(mesh, model);
+        }
+    }
+
+    /// How much of the light reaches `position`, see `ShadowMap::visibility`. Only the face
+    /// `position` is in front of gets asked.
+    pub fn visibility(&self, position: &point::Point3D, lambert: f64) -> f64 {
+        let offset = *position - self.position;
+        let (x, y, z) = (offset.x.abs(), offset.y.abs(), offset.z.abs());
+
+        let face = if x >= y && x >= z {
+            if offset.x >= 0.0 { 0 } else { 1 }
+        } else if y >= z {
+            if offset.y >= 0.0 { 2 } else { 3 }
+        } else if offset.z >= 0.0 {
+            4
+        } else {
+            5
+        };
+
+        self.faces[face].visibility(position, lambert)
+    }
+}
+
+/// Whatever shadow map fits the kind of light
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shadow {
+    /// For directional and spot lights
+    Map(ShadowMap),
+    /// For point lights
+    Cube(CubeShadowMap),
+}
+
+impl Shadow {
+    pub fn visibility(&self, position: &point::Point3D, lambert: f64) -> f64 {
+        match self {
+            Shadow::Map(map) => map.visibility(position, lambert),
+            Shadow::Cube(cube) => cube.visibility(position, lambert),
+        }
+    }
+}
+
+impl From<ShadowMap> for Shadow {
+    fn from(map: ShadowMap) -> Shadow {
+        Shadow::Map(map)
+    }
+}
+
+impl From<CubeShadowMap> for Shadow {
+    fn from(cube: CubeShadowMap) -> Shadow {
+        Shadow::Cube(cube)
+    }
+}
+
+/// For a directional light going `direction`: an orthographic projection that covers a sphere
+/// of `radius` around `center`. The smaller the sphere, the sharper the shadows.
+pub fn directional_view_projection(
+    direction: &vector::Vector3D,
+    center: &point::Point3D,
+    radius: f64) -> matrix::Matrix4
+{
+    let direction = direction.normalized();
+    let eye = *center - direction * (2.0 * radius);
+
+    let view = matrix::Matrix4::look_at(&eye, center, &up_for(&direction));
+    let projection = matrix::Matrix4::orthographic(-radius, radius, -radius, radius, radius, 3.0 * radius);
+
+    projection * view
+}
+
+/// For a spot light at `position` shining along `direction`: a perspective projection that
+/// covers the whole cone
+pub fn spot_view_projection(
+    position: &point::Point3D,
+    direction: &vector::Vector3D,
+    outer_angle: f64,
+    near: f64,
+    far: f64) -> matrix::Matrix4
+{
+    let direction = direction.normalized();
+
+    let view = matrix::Matrix4::look_at(position, &(*position + direction), &up_for(&direction));
+    let projection = matrix::Matrix4::perspective(2.0 * outer_angle, 1.0, near, far);
+
+    projection * view
+}
+
+// look_at needs an up vector that isn't parallel to the direction we're looking in
+fn up_for(direction: &vector::Vector3D) -> vector::Vector3D {
+    if direction.y.abs() > 0.99 {
+        vector::Vector3D::new(0.0, 0.0, 1.0)
+    } else {
+        vector::Vector3D::new(0.0, 1.0, 0.0)
+    }
+}
+
+// Nothing but the position, the depth buffer does the rest
+struct DepthShader {
+    model_view_projection: matrix::Matrix4,
+}
+
+impl VertexShader for DepthShader {
+    type Input = mesh::Vertex;
+    type Varyings = ();
+
+    fn vertex(&self, input: &mesh::Vertex) -> VertexOutput<()> {
+        VertexOutput { position: self.model_view_projection * vector::Vector4::from(input.position), varyings: () }
+    }
+}
+
+impl FragmentShader<()> for DepthShader {
+    type Varyings = ();
+
+    fn fragment(&self, _input: &FragmentInput<()>) -> Fragment<()> {
+        Fragment::Color(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::matrix::Matrix4;
+    use crate::mesh::{Mesh, Vertex};
+    use crate::point::Point3D;
+    use crate::shadow::{directional_view_projection, spot_view_projection, CubeShadowMap, ShadowMap};
+    use crate::vector::Vector3D;
+
+    // A square at height y, from -size to size in x and z
+    fn square(y: f64, size: f64) -> Mesh {
+        let corner = |x: f64, z: f64| Vertex { position: Point3D { x, y, z }, ..Vertex::default() };
+        let (a, b, c, d) = (corner(-size, -size), corner(size, -size), corner(size, size), corner(-size, size));
+
+        Mesh::from_triangles(vec![[a, b, c], [a, c, d]])
+    }
+
+    // A floor, and a small square floating above its middle. The sun shines straight down.
+    fn shadow_map() -> ShadowMap {
+        let down = Vector3D::new(0.0, -1.0, 0.0);
+        let view_projection = directional_view_projection(&down, &Point3D::default(), 4.0);
+
+        let mut map = ShadowMap::new(128, 128, view_projection);
+        map.render(&square(0.0, 4.0), &Matrix4::identity());
+        map.render(&square(0.5, 0.5), &Matrix4::identity());
+        map.render(&square(0.0, 1.0), &Matrix4::translate(0.0, 1.0, 0.0));
+
+        map
+    }
+
+    #[test]
+    fn test_shadows() {
+        let mut map = shadow_map();
+        map.pcf_radius = 0;
+
+        let floor = |x: f64, z: f64| map.visibility(&Point3D { x, y: 0.0, z }, 1.0);
+
+        // Right under the small square, and under the bigger one that got moved up
+        assert_eq!(floor(0.0, 0.0), 0.0);
+        assert_eq!(floor(0.8, -0.8), 0.0);
+        // Out in the open. The floor doesn't shadow itself.
+        assert_eq!(floor(3.0, 3.0), 1.0);
+        assert_eq!(floor(-1.5, 0.0), 1.0);
+        // The top of the small square is in the shadow of the bigger one above it
+        assert_eq!(map.visibility(&Point3D { x: 0.0, y: 0.5, z: 0.0 }, 1.0), 0.0);
+        // The top of the highest square is lit
+        assert_eq!(map.visibility(&Point3D { x: 0.0, y: 1.0, z: 0.0 }, 1.0), 1.0);
+        // Outside of the shadow map everything is lit
+        assert_eq!(floor(10.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_percentage_closer_filtering() {
+        let mut map = shadow_map();
+        map.pcf_radius = 2;
+
+        // Right on the edge of the shadow, part of the texels around are shadowed
+        let edge = map.visibility(&Point3D { x: 1.0, y: 0.0, z: 0.0 }, 1.0);
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+
+        assert_eq!(map.visibility(&Point3D { x: 0.0, y: 0.0, z: 0.0 }, 1.0), 0.0);
+        assert_eq!(map.visibility(&Point3D { x: 3.0, y: 0.0, z: 3.0 }, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_bias() {
+        let mut map = shadow_map();
+        map.pcf_radius = 0;
+        map.bias = 0.0;
+        map.slope_bias = 0.0;
+
+        // A point a hair below the floor counts as shadowed by the floor without bias...
+        let below = Point3D { x: 3.0, y: -0.001, z: 3.0 };
+        assert_eq!(map.visibility(&below, 1.0), 0.0);
+
+        // ...but not with it
+        map.bias = 0.002;
+        assert_eq!(map.visibility(&below, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_spot_light() {
+        // A spot light 3 above the floor shining straight down, with a small square half way
+        let position = Point3D { x: 0.0, y: 3.0, z: 0.0 };
+        let view_projection = spot_view_projection(&position, &Vector3D::new(0.0, -1.0, 0.0), 30.0_f64.to_radians(), 0.5, 10.0);
+
+        let mut map = ShadowMap::new(128, 128, view_projection);
+        map.pcf_radius = 0;
+        map.render(&square(0.0, 4.0), &Matrix4::identity());
+        map.render(&square(1.5, 0.3), &Matrix4::identity());
+
+        let floor = |x: f64, z: f64| map.visibility(&Point3D { x, y: 0.0, z }, 1.0);
+
+        // The shadow spreads out from the light: the square is 0.6 wide, its shadow twice that
+        assert_eq!(floor(0.0, 0.0), 0.0);
+        assert_eq!(floor(0.5, 0.0), 0.0);
+        assert_eq!(floor(0.0, -0.5), 0.0);
+        assert_eq!(floor(0.8, 0.0), 1.0);
+        // Outside of the cone, and behind the light
+        assert_eq!(floor(3.0, 0.0), 1.0);
+        assert_eq!(map.visibility(&Point3D { x: 0.0, y: 4.0, z: 0.0 }, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_point_light() {
+        // A point light 2 above the floor, with small squares around it on every side that
+        // matters: one below it, and one standing up on each side along x
+        let light = Point3D { x: 0.0, y: 2.0, z: 0.0 };
+        let mut cube = CubeShadowMap::new(128, light, 0.1, 20.0);
+
+        let wall = |x: f64| {
+            let corner = |y: f64, z: f64| Vertex { position: Point3D { x, y, z }, ..Vertex::default() };
+            let (a, b, c, d) = (corner(1.5, -0.5), corner(2.5, -0.5), corner(2.5, 0.5), corner(1.5, 0.5));
+
+            Mesh::from_triangles(vec![[a, b, c], [a, c, d]])
+        };
+
+        for face in cube.faces_mut().iter_mut() {
+            face.pcf_radius = 0;
+        }
+
+        cube.render(&square(0.0, 8.0), &Matrix4::identity());
+        cube.render(&square(1.0, 0.3), &Matrix4::identity());
+        cube.render(&wall(1.0), &Matrix4::identity());
+        cube.render(&wall(-1.0), &Matrix4::identity());
+
+        let visibility = |x: f64, y: f64, z: f64| cube.visibility(&Point3D { x, y, z }, 1.0);
+
+        // Straight below, behind the small square
+        assert_eq!(visibility(0.0, 0.0, 0.0), 0.0);
+        assert_eq!(visibility(0.4, 0.0, 0.0), 0.0);
+        // Off to the sides along z nothing is in the way
+        assert_eq!(visibility(0.0, 0.0, 3.0), 1.0);
+        assert_eq!(visibility(0.0, 0.0, -3.0), 1.0);
+        // Behind the walls on both sides, which a single shadow map looking down couldn't see
+        assert_eq!(visibility(3.0, 2.0, 0.0), 0.0);
+        assert_eq!(visibility(-3.0, 2.0, 0.0), 0.0);
+        // Above the light there's nothing
+        assert_eq!(visibility(0.0, 5.0, 0.0), 1.0);
+    }
+}
@@ -0,0 +1,506 @@
+use std::f64::consts::PI;
+use crate::color;
+use crate::depth;
+use crate::image;
+use crate::matrix;
+use crate::point;
+use crate::vector;
+
+/// Screen-space ambient occlusion: a post-process that darkens creases, corners and cavities,
+/// where less of the light bouncing around the scene gets in.
+///
+/// It only needs what the camera pass left behind. Every pixel gets turned back into a point in
+/// view space using the depth buffer, then points in the hemisphere above its surface get checked
+/// against the depth buffer: the more of them end up behind something that was drawn, the more
+/// occluded the pixel is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    /// How many points around every pixel get checked. More samples means less noise, but it's
+    /// slower.
+    pub samples: usize,
+    /// How far around a pixel to look for occluders, in view space units. Small radiuses only
+    /// catch tight creases, bigger ones also darken the space between objects.
+    pub radius: f64,
+    /// How far in front of a sample a surface has to be to occlude it. Keeps flat surfaces from
+    /// occluding themselves through depth imprecision.
+    pub bias: f64,
+    /// How dark `apply` makes fully occluded pixels: 0 leaves the color alone, 1 turns them black
+    pub strength: f64,
+    /// Every pixel uses its own rotation of the samples, which trades banding for noise. The
+    /// blur gets rid of the noise again.
+    pub blur: bool,
+}
+
+// The rotations of the samples repeat every NOISE_SIZE pixels, which is also what the blur
+// averages over
+const NOISE_SIZE: usize = 4;
+
+impl AmbientOcclusion {
+    pub fn new(samples: usize, radius: f64) -> AmbientOcclusion {
+        AmbientOcclusion { samples, radius, bias: radius * 0.025, strength: 1.0, blur: true }
+    }
+
+    /// Work out how much ambient light reaches every pixel: 1 is all of it, 0 is none.
+    ///
+    /// `depth` is the depth buffer of the camera pass, and `projection` the projection it was
+    /// drawn with (without the view matrix). `normals` are the view space normals of the pixels,
+    /// for when the camera pass wrote them to a second color attachment. Without them the normals
+    /// get worked out from the depth buffer.
+    /// Pixels where nothing was drawn get 1.
+    pub fn compute(
+        &self,
+        depth: &depth::DepthBuffer,
+        projection: &matrix::Matrix4,
+        normals: Option<&image::Image<vector::Vector3D>>) -> image::Image<f64>
+    {
+        let (width, height) = (depth.get_width(), depth.get_height());
+        let kernel = kernel(self.samples);
+
+        let Some(inverse) = projection.inverse() else {
+            return image::Image::new(width, height, 1.0);
+        };
+
+        let positions = view_positions(depth, &inverse);
+
+        let mut occlusion = image::Image::new(width, height, 1.0);
+
+        for y in 0..height {
+            for x in 0..width {
+                let Some(position) = positions.get(x, y) else {
+                    continue;
+                };
+
+                let normal = normals
+                    .map(|normals| normals.get(x, y))
+                    .filter(|normal| normal.length() > 0.0)
+                    .map(|normal| normal.normalized())
+                    .or_else(|| reconstruct_normal(&positions, x, y));
+
+                let Some(normal) = normal else {
+                    continue;
+                };
+
+                // Spin the samples around the normal by an angle that changes from pixel to pixel
+                let noise = (y % NOISE_SIZE) * NOISE_SIZE + x % NOISE_SIZE;
+                let angle = 2.0 * PI * noise as f64 / (NOISE_SIZE * NOISE_SIZE) as f64;
+                let (tangent, bitangent) = basis(&normal, angle);
+
+                let mut occluded = 0.0;
+
+                for sample in kernel.iter() {
+                    let offset = tangent * sample.x + bitangent * sample.y + normal * sample.z;
+                    let sample = position + offset * self.radius;
+
+                    occluded += self.occluder(depth, projection, &inverse, &position, &sample);
+                }
+
+                occlusion.set(x, y, 1.0 - occluded / kernel.len().max(1) as f64).unwrap();
+            }
+        }
+
+        if self.blur {
+            blur(&occlusion, &positions)
+        } else {
+            occlusion
+        }
+    }
+
+    // How much the surface drawn where `sample` lands on the screen hides it, from 0 to 1.
+    // Surfaces far in front of the pixel (more than the radius) don't count: they're some other
+    // object passing by, not something close enough to block the light.
+    fn occluder(
+        &self,
+        depth: &depth::DepthBuffer,
+        projection: &matrix::Matrix4,
+        inverse: &matrix::Matrix4,
+        position: &point::Point3D,
+        sample: &point::Point3D) -> f64
+    {
+        let clip = *projection * vector::Vector4::from(*sample);
+
+        if clip.w <= 0.0 {
+            return 0.0;
+        }
+
+        let ndc = clip.to_point();
+        let x = (ndc.x + 1.0) / 2.0 * depth.get_width() as f64;
+        let y = (ndc.y + 1.0) / 2.0 * depth.get_height() as f64;
+
+        let Some(surface) = surface(depth, inverse, x, y) else {
+            return 0.0;
+        };
+
+        // The camera looks down -z, so the bigger z the closer to it
+        if surface.z < sample.z + self.bias {
+            return 0.0;
+        }
+
+        smoothstep(self.radius / (position.z - surface.z).abs())
+    }
+
+    /// Darken `color` where the occlusion from `compute` says little ambient light gets in
+    pub fn apply(&self, color: &mut image::Image<color::Color32>, occlusion: &image::Image<f64>) {
+        for y in 0..color.get_height() {
+            for x in 0..color.get_width() {
+                let factor = 1.0 - self.strength * (1.0 - occlusion.get(x, y));
+
+                color.set(x, y, color.get(x, y).scaled(factor)).unwrap();
+            }
+        }
+    }
+}
+
+// Undo the viewport transform and the projection (`inverse` is the inverse of the projection)
+fn unproject(depth: &depth::DepthBuffer, inverse: &matrix::Matrix4, x: f64, y: f64, value: f64) -> point::Point3D {
+    let ndc = vector::Vector4::new(
+        2.0 * x / depth.get_width() as f64 - 1.0,
+        2.0 * y / depth.get_height() as f64 - 1.0,
+        2.0 * value - 1.0,
+        1.0);
+
+    (*inverse * ndc).to_point()
+}
+
+// The view space position of every pixel that was drawn
+fn view_positions(depth: &depth::DepthBuffer, inverse: &matrix::Matrix4) -> image::Image<Option<point::Point3D>> {
+    let (width, height) = (depth.get_width(), depth.get_height());
+    let mut positions = image::Image::new(width, height, None);
+
+    for y in 0..height {
+        for x in 0..width {
+            let value = depth.get(x, y);
+
+            if value != depth.clear_value {
+                positions.set(x, y, Some(unproject(depth, inverse, x as f64 + 0.5, y as f64 + 0.5, value))).unwrap();
+            }
+        }
+    }
+
+    positions
+}
+
+// The view space position of whatever was drawn at window coordinates (x, y), which don't
+// have to land on a pixel. Depth after the perspective divide changes linearly across a flat
+// surface, so interpolating it between the four pixels around gets flat surfaces exactly right.
+// Going with the closest pixel instead would make tilted surfaces occlude themselves in steps.
+// Right on the edge of what was drawn there's nothing to interpolate, and we say there's
+// nothing there.
+fn surface(depth: &depth::DepthBuffer, inverse: &matrix::Matrix4, x: f64, y: f64) -> Option<point::Point3D> {
+    let (width, height) = (depth.get_width(), depth.get_height());
+
+    if x < 0.0 || y < 0.0 || x >= width as f64 || y >= height as f64 {
+        return None;
+    }
+
+    // Pixels are sampled at their centers, so the four pixels around are the ones whose centers
+    // are around
+    let (px, py) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (px.floor().max(0.0) as usize, py.floor().max(0.0) as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (tx, ty) = ((px - x0 as f64).clamp(0.0, 1.0), (py - y0 as f64).clamp(0.0, 1.0));
+
+    let corners = [depth.get(x0, y0), depth.get(x1, y0), depth.get(x0, y1), depth.get(x1, y1)];
+
+    if corners.contains(&depth.clear_value) {
+        return None;
+    }
+
+    let bottom = corners[0] + (corners[1] - corners[0]) * tx;
+    let top = corners[2] + (corners[3] - corners[2]) * tx;
+    let value = bottom + (top - bottom) * ty;
+
+    Some(unproject(depth, inverse, x, y, value))
+}
+
+// The normal of the surface at a pixel, from the positions of its neighbours. On each axis we
+// take the neighbour closest in depth, so pixels on the edge of an object don't get a normal
+// that's half way to the background.
+fn reconstruct_normal(positions: &image::Image<Option<point::Point3D>>, x: usize, y: usize) -> Option<vector::Vector3D> {
+    let center = positions.get(x, y)?;
+
+    let neighbour = |dx: i64, dy: i64| {
+        let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+
+        if nx < 0 || ny < 0 || nx as usize >= positions.get_width() || ny as usize >= positions.get_height() {
+            return None;
+        }
+
+        positions.get(nx as usize, ny as usize).map(|p| p - center)
+    };
+
+    let closest = |a: Option<vector::Vector3D>, b: Option<vector::Vector3D>| match (a, b) {
+        (Some(a), Some(b)) => Some(if a.z.abs() <= b.z.abs() { a } else { -b }),
+        (Some(a), None) => Some(a),
+        (None, Some(b)) => Some(-b),
+        (None, None) => None,
+    };
+
+    let along_x = closest(neighbour(1, 0), neighbour(-1, 0))?;
+    let along_y = closest(neighbour(0, 1), neighbour(0, -1))?;
+
+    let normal = vector::cross_product(&along_x, &along_y);
+
+    if normal.length() == 0.0 {
+        return None;
+    }
+
+    // Facing the camera, which sits at the origin
+    let normal = normal.normalized();
+    let to_camera = point::Point3D::default() - center;
+
+    Some(if normal.dot(&to_camera) < 0.0 { -normal } else { normal })
+}
+
+// Two unit vectors perpendicular to the normal and to each other, turned by `angle`
+fn basis(normal: &vector::Vector3D, angle: f64) -> (vector::Vector3D, vector::Vector3D) {
+    let helper = if normal.x.abs() < 0.9 {
+        vector::Vector3D::new(1.0, 0.0, 0.0)
+    } else {
+        vector::Vector3D::new(0.0, 1.0, 0.0)
+    };
+
+    let u = vector::cross_product(&helper, normal).normalized();
+    let v = vector::cross_product(normal, &u);
+
+    let tangent = u * angle.cos() + v * angle.sin();
+
+    (tangent, vector::cross_product(normal, &tangent))
+}
+
+// xorshift: numbers that look random enough, but are the same every time for the same seed.
+// Saves pulling in a dependency.
+pub(crate) struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    // The seed can be anything but 0
+    pub(crate) fn new(seed: u64) -> Xorshift {
+        Xorshift { state: seed }
+    }
+
+    // In [0, 1)
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        // The top 53 bits are as many as an f64 can hold
+        (self.state >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+// Points in the unit hemisphere around +z. There are a few more of them close to the center,
+// where occluders matter the most. The same every time: a fixed xorshift sequence, so the output
+// doesn't flicker from frame to frame.
+fn kernel(samples: usize) -> Vec<vector::Vector3D> {
+    let mut generator = Xorshift::new(0x2545_f491_4f6c_dd1d);
+    let mut random = || generator.next_f64();
+
+    (0..samples)
+        .map(|_| {
+            // Uniform over the hemisphere, then pulled in towards the center
+            let z = random();
+            let angle = 2.0 * PI * random();
+            let r = (1.0 - z * z).sqrt();
+            let direction = vector::Vector3D::new(r * angle.cos(), r * angle.sin(), z);
+
+            // The square root puts more of them close in than the cube root, which would
+            // spread them evenly over the volume
+            direction * random().powf(0.5)
+        })
+        .collect()
+}
+
+// Average over NOISE_SIZE x NOISE_SIZE blocks, which is exactly one full turn of the sample
+// rotations. Pixels where nothing was drawn are left out.
+fn blur(occlusion: &image::Image<f64>, positions: &image::Image<Option<point::Point3D>>) -> image::Image<f64> {
+    let (width, height) = (occlusion.get_width(), occlusion.get_height());
+    let mut result = occlusion.clone();
+    let half = NOISE_SIZE as i64 / 2;
+
+    for y in 0..height {
+        for x in 0..width {
+            if positions.get(x, y).is_none() {
+                continue;
+            }
+
+            let (mut sum, mut count) = (0.0, 0);
+
+            for ny in y as i64 - half..y as i64 - half + NOISE_SIZE as i64 {
+                for nx in x as i64 - half..x as i64 - half + NOISE_SIZE as i64 {
+                    if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                        continue;
+                    }
+
+                    if positions.get(nx as usize, ny as usize).is_some() {
+                        sum += occlusion.get(nx as usize, ny as usize);
+                        count += 1;
+                    }
+                }
+            }
+
+            result.set(x, y, sum / count as f64).unwrap();
+        }
+    }
+
+    result
+}
+
+fn smoothstep(x: f64) -> f64 {
+    let x = x.clamp(0.0, 1.0);
+
+    x * x * (3.0 - 2.0 * x)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::color::Color32;
+    use crate::drawing;
+    use crate::framebuffer::Framebuffer;
+    use crate::image::Image;
+    use crate::matrix::Matrix4;
+    use crate::point::Point3D;
+    use crate::shader::{Fragment, FragmentInput, FragmentShader, VertexOutput, VertexShader};
+    use crate::ssao::AmbientOcclusion;
+    use crate::vector::{Vector3D, Vector4};
+
+    const SIZE: usize = 64;
+
+    // The camera sits at the origin looking down -z, so there's no view matrix
+    struct DepthShader {
+        projection: Matrix4,
+    }
+
+    impl VertexShader for DepthShader {
+        type Input = Point3D;
+        type Varyings = ();
+
+        fn vertex(&self, input: &Point3D) -> VertexOutput<()> {
+            VertexOutput { position: self.projection * Vector4::from(*input), varyings: () }
+        }
+    }
+
+    impl FragmentShader<()> for DepthShader {
+        type Varyings = ();
+
+        fn fragment(&self, _input: &FragmentInput<()>) -> Fragment<()> {
+            Fragment::Color(())
+        }
+    }
+
+    fn projection() -> Matrix4 {
+        Matrix4::perspective(60.0_f64.to_radians(), 1.0, 0.5, 50.0)
+    }
+
+    // Draw quads given by their corners, and keep the depth
+    fn render(quads: &[[Point3D; 4]]) -> Framebuffer<()> {
+        let shader = DepthShader { projection: projection() };
+        let mut framebuffer = Framebuffer::depth_only(SIZE, SIZE);
+
+        let triangles = quads.iter().flat_map(|&[a, b, c, d]| [[a, b, c], [a, c, d]]);
+        drawing::draw_triangles(&shader, &shader, triangles, &drawing::RasterizerState::default(), &mut framebuffer);
+
+        framebuffer
+    }
+
+    // Where a point ends up on the screen
+    fn pixel(point: &Point3D) -> (usize, usize) {
+        let window = Matrix4::viewport(0.0, 0.0, SIZE as f64, SIZE as f64).transform_point(&projection().transform_point(point));
+
+        (window.x.floor() as usize, window.y.floor() as usize)
+    }
+
+    fn point(x: f64, y: f64, z: f64) -> Point3D {
+        Point3D { x, y, z }
+    }
+
+    #[test]
+    fn test_flat_surface_is_not_occluded() {
+        // A wall facing the camera, covering the whole screen, and one that's tilted
+        let facing = render(&[[point(-10.0, -10.0, -5.0), point(10.0, -10.0, -5.0), point(10.0, 10.0, -5.0), point(-10.0, 10.0, -5.0)]]);
+        let tilted = render(&[[point(-10.0, -2.0, -2.0), point(10.0, -2.0, -2.0), point(10.0, 2.0, -20.0), point(-10.0, 2.0, -20.0)]]);
+
+        let ao = AmbientOcclusion::new(16, 0.5);
+
+        for framebuffer in [facing, tilted] {
+            let occlusion = ao.compute(framebuffer.depth.as_ref().unwrap(), &projection(), None);
+            let darkest = occlusion.pixels().iter().cloned().fold(1.0, f64::min);
+
+            assert!(darkest > 0.95, "{}", darkest);
+        }
+
+        // Handing the normals over gives the same
+        let framebuffer = render(&[[point(-10.0, -10.0, -5.0), point(10.0, -10.0, -5.0), point(10.0, 10.0, -5.0), point(-10.0, 10.0, -5.0)]]);
+        let normals = Image::new(SIZE, SIZE, Vector3D::new(0.0, 0.0, 1.0));
+        let occlusion = ao.compute(framebuffer.depth.as_ref().unwrap(), &projection(), Some(&normals));
+
+        assert!(occlusion.pixels().iter().all(|&value| value > 0.95));
+    }
+
+    #[test]
+    fn test_crease_is_occluded() {
+        // A groove running up and down, facing the camera. Both sides are at 45 degrees.
+        let left = [point(-2.0, -5.0, -4.0), point(0.0, -5.0, -6.0), point(0.0, 5.0, -6.0), point(-2.0, 5.0, -4.0)];
+        let right = [point(0.0, -5.0, -6.0), point(2.0, -5.0, -4.0), point(2.0, 5.0, -4.0), point(0.0, 5.0, -6.0)];
+
+        let framebuffer = render(&[left, right]);
+        let depth = framebuffer.depth.as_ref().unwrap();
+
+        for normals in [false, true] {
+            let ao = AmbientOcclusion { blur: normals, ..AmbientOcclusion::new(32, 1.0) };
+
+            let normals = normals.then(|| {
+                let (crease, _) = pixel(&point(0.0, 0.0, -6.0));
+                let mut image = Image::new(SIZE, SIZE, Vector3D::new(-1.0, 0.0, 1.0));
+
+                for y in 0..SIZE {
+                    for x in 0..crease {
+                        image.set(x, y, Vector3D::new(1.0, 0.0, 1.0)).unwrap();
+                    }
+                }
+
+                image
+            });
+
+            let occlusion = ao.compute(depth, &projection(), normals.as_ref());
+            let at = |p: Point3D| {
+                let (x, y) = pixel(&p);
+                occlusion.get(x, y)
+            };
+
+            // Close to the bottom of the groove, and far enough up the sides
+            let crease = at(point(0.1, 0.0, -5.9));
+            let left_side = at(point(-1.6, 0.0, -4.4));
+            let right_side = at(point(1.6, 0.0, -4.4));
+
+            assert!(crease < 0.8, "{}", crease);
+            assert!(left_side > 0.95, "{}", left_side);
+            assert!(right_side > 0.95, "{}", right_side);
+        }
+
+        // Nothing was drawn at the edge of the screen
+        let occlusion = AmbientOcclusion::new(8, 1.0).compute(depth, &projection(), None);
+        assert_eq!(occlusion.get(0, SIZE / 2), 1.0);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut occlusion = Image::new(2, 1, 1.0);
+        occlusion.set(1, 0, 0.25).unwrap();
+
+        let mut color = Image::new(2, 1, Color32::new(200, 100, 40, 128));
+        let mut ao = AmbientOcclusion::new(8, 1.0);
+
+        ao.strength = 0.5;
+        ao.apply(&mut color, &occlusion);
+
+        assert_eq!(color.get(0, 0), Color32::new(200, 100, 40, 128));
+        assert_eq!(color.get(1, 0), Color32::new(125, 62, 25, 128));
+
+        // The AO only image
+        let gray = occlusion.to_color32();
+        assert_eq!(gray.get(0, 0), Color32::new(255, 255, 255, 255));
+        assert_eq!(gray.get(1, 0), Color32::new(64, 64, 64, 255));
+    }
+}
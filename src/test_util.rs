@@ -0,0 +1,10 @@
+// Things the tests of more than one module need
+use crate::ssao::Xorshift;
+
+// Numbers in [0, 1) that look random, but come out the same on every run. Different seeds give
+// different sequences.
+pub fn random(seed: u64) -> impl FnMut() -> f64 {
+    let mut generator = Xorshift::new(seed);
+
+    move || generator.next_f64()
+}
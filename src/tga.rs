@@ -226,15 +226,15 @@ impl<'a> TGAFileParser<'a> {
         Ok(TGAFile {
             id_length: 0,
             color_map_type: 0,
-            image_type: image_type,
+            image_type,
             color_map_spec: [0; 5],
-            x_origin: x_origin,
-            y_origin: y_origin,
-            image_width: image_width,
-            image_height: image_height,
+            x_origin,
+            y_origin,
+            image_width,
+            image_height,
             image_bits_per_pixel: pixel_depth,
             image_data: pixel_data,
-            image_descriptor: image_descriptor,
+            image_descriptor,
             extension_area_offset: [0; 4],
             developer_dictionary_offset: [0; 4],
             signature: [0; 18] 
@@ -345,13 +345,12 @@ impl<'a> TGAFileParser<'a> {
                     let b = self.read_u8(msg)?;
                     let g = self.read_u8(msg)?;
                     let r = self.read_u8(msg)?;
-                    let a;
-                    if pixel_depth == 24 {
-                        a = 255;
+                    let a = if pixel_depth == 24 {
+                        255
                     }
                     else {
-                        a = self.read_u8(msg)?;
-                    }
+                        self.read_u8(msg)?
+                    };
 
                     let pixel = u32::from_be_bytes([r, g, b, a]);
 
@@ -0,0 +1,106 @@
+use std::iter::Enumerate;
+use std::str::Lines;
+
+/// A single whitespace separated word of a Wavefront (.obj or .mtl) file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    /// 1-indexed line the token is on
+    pub line: usize,
+    /// 1-indexed column of the first character of the token
+    pub column: usize,
+}
+
+/// Splits the contents of a Wavefront file into statements, one `Vec<Token>` at a time.
+///
+/// The rules are the ones from the spec:
+/// - Tokens are separated by any amount of any kind of whitespace (spaces, tabs, ...)
+/// - `#` starts a comment that runs to the end of the line
+/// - A `\` at the end of a line joins it with the next one
+///
+/// Empty lines and lines with only a comment are skipped. The first token of a statement is its
+/// keyword (`v`, `f`, `usemtl`, ...).
+pub struct Tokenizer<'a> {
+    lines: Enumerate<Lines<'a>>,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(contents: &'a str) -> Tokenizer<'a> {
+        Tokenizer { lines: contents.lines().enumerate() }
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Vec<Token<'a>>;
+
+    fn next(&mut self) -> Option<Vec<Token<'a>>> {
+        let mut statement = Vec::new();
+
+        for (index, line) in self.lines.by_ref() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+
+            let line = line.trim_end();
+            let (line, continued) = match line.strip_suffix('\\') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+
+            statement.extend(tokens(line, index + 1));
+
+            if !continued && !statement.is_empty() {
+                return Some(statement);
+            }
+        }
+
+        // A continuation on the last line of the file still ends the statement
+        if statement.is_empty() { None } else { Some(statement) }
+    }
+}
+
+fn tokens(line: &str, line_number: usize) -> impl Iterator<Item = Token<'_>> {
+    line.split(char::is_whitespace)
+        .filter(|text| !text.is_empty())
+        .map(move |text| Token {
+            text,
+            line: line_number,
+            // Every token is a slice of `line`, so the distance between the two is the offset
+            column: line[..text.as_ptr() as usize - line.as_ptr() as usize].chars().count() + 1,
+        })
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::tokenizer::{Token, Tokenizer};
+
+    fn texts(contents: &str) -> Vec<Vec<&str>> {
+        Tokenizer::new(contents).map(|statement| statement.iter().map(|t| t.text).collect()).collect()
+    }
+
+    #[test]
+    fn test_whitespace_and_comments() {
+        let contents = "# header\n\nv\t1  2 \t3 # trailing\r\n   \nf 1 2 3";
+
+        assert_eq!(texts(contents), vec![vec!["v", "1", "2", "3"], vec!["f", "1", "2", "3"]]);
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let contents = "f 1 2 \\\n  3 4\\\n5\nv 0 0 0 \\";
+
+        assert_eq!(texts(contents), vec![vec!["f", "1", "2", "3", "4", "5"], vec!["v", "0", "0", "0"]]);
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let statement = Tokenizer::new("\n\tvt  0.5 \\\n 1").next().unwrap();
+
+        assert_eq!(statement, vec![
+            Token { text: "vt", line: 2, column: 2 },
+            Token { text: "0.5", line: 2, column: 6 },
+            Token { text: "1", line: 3, column: 2 },
+        ]);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::point;
+use crate::vector;
+
+/// Split a polygon into triangles.
+///
+/// `points` are the corners of the polygon, in order. The result holds triples of indices into
+/// `points`, wound the same way as the polygon. A polygon with n corners always gives n - 2
+/// triangles.
+///
+/// Convex polygons are split into a fan around the first corner. Concave ones go through ear
+/// clipping. Polygons don't have to lie in the xy plane, but they are expected to be (roughly)
+/// flat.
+pub fn triangulate(points: &[point::Point3D]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+
+    let normal = polygon_normal(points);
+
+    if is_convex(points, &normal) {
+        fan(&(0..points.len()).collect::<Vec<usize>>())
+    }
+    else {
+        ear_clipping(points, &normal)
+    }
+}
+
+// Newell's method. Works for concave polygons too, and the normal points the way a
+// counter-clockwise polygon would have it point (right-hand rule).
+pub(crate) fn polygon_normal(points: &[point::Point3D]) -> vector::Vector3D {
+    let mut normal = vector::Vector3D::default();
+
+    for (i, current) in points.iter().enumerate() {
+        let next = points[(i + 1) % points.len()];
+
+        normal.x += (current.y - next.y) * (current.z + next.z);
+        normal.y += (current.z - next.z) * (current.x + next.x);
+        normal.z += (current.x - next.x) * (current.y + next.y);
+    }
+
+    normal
+}
+
+// Which way does the polygon turn at corner b? Positive if it turns the same way the polygon as
+// a whole does (b is convex), negative if it turns the other way (b is a reflex corner).
+fn turn(a: &point::Point3D, b: &point::Point3D, c: &point::Point3D, normal: &vector::Vector3D) -> f64 {
+    vector::cross_product(&(*b - *a), &(*c - *b)).dot(normal)
+}
+
+fn is_convex(points: &[point::Point3D], normal: &vector::Vector3D) -> bool {
+    let n = points.len();
+
+    (0..n).all(|i| turn(&points[(i + n - 1) % n], &points[i], &points[(i + 1) % n], normal) >= 0.0)
+}
+
+fn fan(indices: &[usize]) -> Vec<[usize; 3]> {
+    (1..indices.len() - 1)
+        .map(|i| [indices[0], indices[i], indices[i + 1]])
+        .collect()
+}
+
+// Is p inside (or on the border of) the triangle abc?
+// Same idea as in the rasterizer: p is inside if it's on the inner side of all three edges.
+fn in_triangle(
+    p: &point::Point3D,
+    a: &point::Point3D,
+    b: &point::Point3D,
+    c: &point::Point3D,
+    normal: &vector::Vector3D) -> bool
+{
+    turn(a, b, p, normal) >= 0.0 && turn(b, c, p, normal) >= 0.0 && turn(c, a, p, normal) >= 0.0
+}
+
+// An "ear" is a convex corner whose triangle (with its two neighbours) doesn't contain any other
+// corner of the polygon. Every simple polygon with more than 3 corners has at least two of them.
+// Cutting an ear off leaves a polygon with one less corner, so we keep cutting until only a
+// triangle is left.
+fn ear_clipping(points: &[point::Point3D], normal: &vector::Vector3D) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+
+            turn(&points[a], &points[b], &points[c], normal) > 0.0
+                && remaining.iter()
+                    .filter(|&&other| other != a && other != b && other != c)
+                    .all(|&other| !in_triangle(&points[other], &points[a], &points[b], &points[c], normal))
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]]);
+                remaining.remove(i);
+            },
+            // Only self-intersecting or degenerate polygons get here. There's no right answer for
+            // those, so we do what we can.
+            None => break,
+        }
+    }
+
+    triangles.extend(fan(&remaining));
+
+    triangles
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::point::Point3D;
+    use crate::triangulation::triangulate;
+    use crate::vector::{cross_product, Vector3D};
+
+    fn points(coordinates: &[(f64, f64)]) -> Vec<Point3D> {
+        coordinates.iter().map(|&(x, y)| Point3D { x, y, z: 0.0 }).collect()
+    }
+
+    // Signed area of a triangle in the xy plane, positive if counter-clockwise
+    fn area(points: &[Point3D], triangle: &[usize; 3]) -> f64 {
+        let [a, b, c] = *triangle;
+        cross_product(&(points[b] - points[a]), &(points[c] - points[a])).dot(&Vector3D::new(0.0, 0.0, 1.0)) / 2.0
+    }
+
+    #[test]
+    fn test_convex_quad_is_a_fan() {
+        let quad = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+
+        assert_eq!(triangulate(&quad), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn test_concave_polygon() {
+        // An arrow head pointing up, with the notch at (1, 1). A fan around the first corner
+        // would cover the notch.
+        let arrow = points(&[(0.0, 0.0), (1.0, 1.0), (2.0, 0.0), (1.0, 3.0)]);
+
+        let triangles = triangulate(&arrow);
+        assert_eq!(triangles.len(), 2);
+
+        let total: f64 = triangles.iter().map(|t| area(&arrow, t)).sum();
+        assert!((total - 2.0).abs() < 1e-9);
+
+        for triangle in triangles.iter() {
+            assert!(area(&arrow, triangle) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_clockwise_concave_polygon() {
+        // An L shape, going clockwise
+        let l_shape = points(&[(0.0, 0.0), (0.0, 2.0), (1.0, 2.0), (1.0, 1.0), (2.0, 1.0), (2.0, 0.0)]);
+
+        let triangles = triangulate(&l_shape);
+        assert_eq!(triangles.len(), 4);
+
+        for triangle in triangles.iter() {
+            assert!(area(&l_shape, triangle) < 0.0);
+        }
+
+        let total: f64 = triangles.iter().map(|t| area(&l_shape, t)).sum();
+        assert!((total + 3.0).abs() < 1e-9);
+    }
+}
@@ -1,65 +1,218 @@
-use std::ops::Mul;
+use std::ops::{Add, Sub, Neg, Mul, Div, Index, IndexMut};
+
+use crate::point;
 
 // 2-dimensional vectors
-#[derive(Copy, Clone)]
-pub struct Vector2D { 
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Vector2D {
     pub x: f64,
     pub y: f64
 }
 
 // 3-dimensional vectors
-#[derive(Copy, Clone)]
-pub struct Vector3D { 
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Vector3D {
     pub x: f64,
     pub y: f64,
     pub z: f64
 }
 
-// Methods for finding the length of the vector
+// 4-dimensional vectors, mostly used as homogeneous coordinates.
+// A point (x, y, z) is (x, y, z, 1) and a direction (x, y, z) is (x, y, z, 0).
+// Any (x, y, z, w) with w != 0 stands for the point (x/w, y/w, z/w).
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Vector4 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub w: f64
+}
+
+// All the vectors behave the same way component by component, so the operators are generated by a
+// macro instead of being written out three times.
+macro_rules! impl_vector {
+    ($name:ident { $($field:ident : $idx:tt),+ }) => {
+        impl $name {
+            pub fn new($($field: f64),+) -> $name {
+                $name { $($field),+ }
+            }
+
+            pub fn dot(&self, other: &$name) -> f64 {
+                0.0 $(+ self.$field * other.$field)+
+            }
+
+            pub fn length(&self) -> f64 {
+                self.dot(self).sqrt()
+            }
+
+            pub fn normalized(&self) -> $name {
+                *self * (1.0/self.length())
+            }
+
+            // Linear interpolation: `self` when t is 0, `other` when t is 1
+            pub fn lerp(&self, other: &$name, t: f64) -> $name {
+                *self + (*other - *self) * t
+            }
+
+            // Component-wise minimum
+            pub fn min(&self, other: &$name) -> $name {
+                $name { $($field: self.$field.min(other.$field)),+ }
+            }
+
+            // Component-wise maximum
+            pub fn max(&self, other: &$name) -> $name {
+                $name { $($field: self.$field.max(other.$field)),+ }
+            }
+
+            // Reflect the vector about `normal`, which must be normalized.
+            // This is what happens to a ray of light hitting a mirror: the part of the vector
+            // along the normal gets flipped, the rest stays the same.
+            pub fn reflect(&self, normal: &$name) -> $name {
+                *self - *normal * (2.0 * self.dot(normal))
+            }
+        }
+
+        impl Add for $name {
+            type Output = $name;
+
+            fn add(self, other: $name) -> $name {
+                $name { $($field: self.$field + other.$field),+ }
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+
+            fn sub(self, other: $name) -> $name {
+                $name { $($field: self.$field - other.$field),+ }
+            }
+        }
+
+        impl Neg for $name {
+            type Output = $name;
+
+            fn neg(self) -> $name {
+                $name { $($field: -self.$field),+ }
+            }
+        }
+
+        // Scalar multiplication
+        impl Mul<f64> for $name {
+            type Output = $name;
+
+            fn mul(self, scalar: f64) -> $name {
+                $name { $($field: self.$field * scalar),+ }
+            }
+        }
+
+        impl Mul<$name> for f64 {
+            type Output = $name;
+
+            fn mul(self, vector: $name) -> $name {
+                vector * self
+            }
+        }
+
+        // Component-wise multiplication
+        impl Mul for $name {
+            type Output = $name;
+
+            fn mul(self, other: $name) -> $name {
+                $name { $($field: self.$field * other.$field),+ }
+            }
+        }
+
+        // Scalar division
+        impl Div<f64> for $name {
+            type Output = $name;
+
+            fn div(self, scalar: f64) -> $name {
+                $name { $($field: self.$field / scalar),+ }
+            }
+        }
+
+        impl Index<usize> for $name {
+            type Output = f64;
+
+            fn index(&self, index: usize) -> &f64 {
+                match index {
+                    $($idx => &self.$field,)+
+                    _ => panic!("Index {} is out of bounds for {}", index, stringify!($name))
+                }
+            }
+        }
+
+        impl IndexMut<usize> for $name {
+            fn index_mut(&mut self, index: usize) -> &mut f64 {
+                match index {
+                    $($idx => &mut self.$field,)+
+                    _ => panic!("Index {} is out of bounds for {}", index, stringify!($name))
+                }
+            }
+        }
+    };
+}
+
+impl_vector!(Vector2D { x: 0, y: 1 });
+impl_vector!(Vector3D { x: 0, y: 1, z: 2 });
+impl_vector!(Vector4 { x: 0, y: 1, z: 2, w: 3 });
+
 impl Vector3D {
-    pub fn normalized(&self) -> Vector3D {
-        *self * (1.0/self.length())
+    // The homogeneous version of this vector with the given w component
+    pub fn extend(&self, w: f64) -> Vector4 {
+        Vector4 { x: self.x, y: self.y, z: self.z, w }
     }
-    pub fn length(&self) -> f64 {
-        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+
+    // The point you end up at if you start at the origin and follow this vector
+    pub fn to_point(&self) -> point::Point3D {
+        point::Point3D { x: self.x, y: self.y, z: self.z }
     }
 }
 
 impl Vector2D {
-    pub fn normalized(&self) -> Vector2D {
-        *self * (1.0/self.length())
+    pub fn to_point(&self) -> point::Point2D {
+        point::Point2D { x: self.x, y: self.y }
+    }
+}
+
+impl Vector4 {
+    // Drop the w component
+    pub fn xyz(&self) -> Vector3D {
+        Vector3D { x: self.x, y: self.y, z: self.z }
     }
-    pub fn length(&self) -> f64 {
-        (self.x * self.x + self.y * self.y).sqrt()
+
+    // Go back from homogeneous coordinates to a 3D point by dividing by w
+    pub fn to_point(&self) -> point::Point3D {
+        point::Point3D { x: self.x / self.w, y: self.y / self.w, z: self.z / self.w }
     }
 }
 
-// Scalar multiplication 
-impl Mul<f64> for Vector3D {
-    type Output = Vector3D;
+impl From<Vector3D> for Vector4 {
+    fn from(v: Vector3D) -> Vector4 {
+        v.extend(0.0)
+    }
+}
 
-    fn mul(self, scalar: f64) -> Vector3D {
-        Vector3D {
-            x: self.x * scalar,
-            y: self.y * scalar,
-            z: self.z * scalar
-        }
+impl From<point::Point3D> for Vector4 {
+    fn from(p: point::Point3D) -> Vector4 {
+        Vector4 { x: p.x, y: p.y, z: p.z, w: 1.0 }
     }
 }
 
-impl Mul<f64> for Vector2D {
-    type Output = Vector2D;
+impl From<point::Point3D> for Vector3D {
+    fn from(p: point::Point3D) -> Vector3D {
+        Vector3D { x: p.x, y: p.y, z: p.z }
+    }
+}
 
-    fn mul(self, scalar: f64) -> Vector2D {
-        Vector2D {
-            x: self.x * scalar,
-            y: self.y * scalar
-        }
+impl From<point::Point2D> for Vector2D {
+    fn from(p: point::Point2D) -> Vector2D {
+        Vector2D { x: p.x, y: p.y }
     }
 }
 
 // Perform the cross product of two 3-dimensional vectors
-// The Copy trait is specified to ensure that the concrete types 
+// The Copy trait is specified to ensure that the concrete types
 // don't need to be "moved"
 pub fn cross_product(a: &Vector3D, b: &Vector3D) -> Vector3D
 {
@@ -69,3 +222,53 @@ pub fn cross_product(a: &Vector3D, b: &Vector3D) -> Vector3D
         z: a.x * b.y - a.y * b.x
     }
 }
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::vector::{Vector3D, Vector4};
+    use crate::point::Point3D;
+
+    #[test]
+    fn test_reflect_flips_normal_component() {
+        let incoming = Vector3D::new(1.0, -1.0, 0.0);
+        let normal = Vector3D::new(0.0, 1.0, 0.0);
+
+        assert_eq!(incoming.reflect(&normal), Vector3D::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_lerp_min_max() {
+        let a = Vector3D::new(0.0, 4.0, -2.0);
+        let b = Vector3D::new(2.0, 0.0, 2.0);
+
+        assert_eq!(a.lerp(&b, 0.5), Vector3D::new(1.0, 2.0, 0.0));
+        assert_eq!(a.min(&b), Vector3D::new(0.0, 0.0, -2.0));
+        assert_eq!(a.max(&b), Vector3D::new(2.0, 4.0, 2.0));
+    }
+
+    #[test]
+    fn test_indexing() {
+        let mut v = Vector4::new(1.0, 2.0, 3.0, 4.0);
+        v[3] = 8.0;
+
+        assert_eq!(v[0] + v[1] + v[2], 6.0);
+        assert_eq!(v.w, 8.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_bounds_panics() {
+        let v = Vector3D::new(1.0, 2.0, 3.0);
+
+        let _ = v[3];
+    }
+
+    #[test]
+    fn test_homogeneous_round_trip() {
+        let p = Point3D { x: 1.0, y: -2.0, z: 3.0 };
+
+        assert_eq!(Vector4::from(p).w, 1.0);
+        assert_eq!(Vector4::from(Vector3D::from(p)).w, 0.0);
+        assert_eq!((Vector4::from(p) * 2.0).to_point(), p);
+    }
+}
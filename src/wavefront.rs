@@ -1,101 +1,301 @@
+use std::error;
+use std::fmt;
 use std::fs;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
 use std::str::FromStr;
-use std::string::ToString;
-use regex::Regex;
+use crate::material;
 use crate::point;
+use crate::tokenizer;
+use crate::triangulation;
+use crate::vector;
 
 
+#[derive(Debug, PartialEq)]
 pub struct WaveFrontFile {
     vertices: Vec<point::Point3D>,
+    polygons: Vec<Polygon>,
     faces: Vec<Face>,
-    textures: Vec<point::Point2D>
+    textures: Vec<point::Point2D>,
+    normals: Vec<vector::Vector3D>,
+    materials: Vec<material::Material>,
+    // File names from the mtllib statements, as they appear in the file
+    material_libraries: Vec<String>,
+    // The ones `new` couldn't read
+    missing_material_libraries: Vec<String>,
+    // Whether there were any s statements at all, even if they were all `s off`
+    has_smoothing_groups: bool,
+    objects: Vec<Group>,
+    groups: Vec<Group>
 }
 
+// A face exactly as it appears in the file, with any number of corners.
+// Texture coordinates and normals are optional in the file (`f 1 2 3`, `f 1/1 2/2 3/3`,
+// `f 1//1 2//2 3//3`). They are only kept if every corner of the polygon has one.
+#[derive(Debug, PartialEq)]
+pub struct Polygon {
+    pub vertices: Vec<usize>,
+    pub textures: Option<Vec<usize>>,
+    pub normals: Option<Vec<usize>>,
+    // Index of the material from the last usemtl statement before the face
+    pub material: Option<usize>,
+    // From the last s statement before the face. `s off` and `s 0` mean no smoothing group.
+    pub smoothing_group: Option<u32>
+}
+
+// A triangle. Polygons with more than 3 corners get split into several of these.
+#[derive(Debug, PartialEq)]
 pub struct Face {
     pub vertices: [usize; 3],
-    pub textures: [usize; 3]
+    pub textures: Option<[usize; 3]>,
+    pub normals: Option<[usize; 3]>,
+    pub material: Option<usize>,
+    // The polygon this triangle is a part of
+    pub polygon: usize
+}
+
+// A run of consecutive faces that share a name, from an o (object) or g (group) statement.
+// A group statement can name several groups, and a name can come back later in the file, so
+// the same name can show up in more than one `Group`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub name: String,
+    pub polygons: Range<usize>,
+    // The triangles the polygons were split into
+    pub faces: Range<usize>
+}
+
+// How much a polygon counts towards the smooth normal of a vertex it shares with others
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NormalWeighting {
+    // Big polygons count more
+    Area,
+    // Polygons count by how wide their corner at the vertex is. Unlike area weighting this
+    // doesn't change when a polygon gets split into smaller ones.
+    Angle,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NormalOptions {
+    pub weighting: NormalWeighting,
+    // Polygons only get smoothed together if their normals are less than this far apart, in
+    // radians. PI smooths everything, 0 gives flat shading.
+    pub crease_angle: f64,
+    // Only smooth polygons in the same smoothing group, and keep polygons outside of any
+    // smoothing group flat. Otherwise only the crease angle counts.
+    // Files without any s statements don't say anything about smoothing, so for those only the
+    // crease angle counts either way.
+    pub use_smoothing_groups: bool,
+}
+
+impl Default for NormalOptions {
+    fn default() -> NormalOptions {
+        NormalOptions { weighting: NormalWeighting::Angle, crease_angle: std::f64::consts::PI, use_smoothing_groups: true }
+    }
+}
+
+// Everything the file says about one corner of a face
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Corner {
+    pub position: point::Point3D,
+    pub texture: Option<point::Point2D>,
+    pub normal: Option<vector::Vector3D>
 }
 
 impl WaveFrontFile {
-    pub fn new(filename: &str) -> Result<WaveFrontFile, String> {
-        let contents = match fs::read_to_string(filename) {
-            Ok(c) => c,
-            Err(e) => e.to_string()
-        };
+    pub fn new(filename: &str) -> Result<WaveFrontFile, ObjError> {
+        let contents = fs::read_to_string(filename).map_err(|e| ObjError {
+            file: Some(filename.to_string()),
+            line: 0,
+            column: 0,
+            token: String::new(),
+            kind: ObjErrorKind::Io(e),
+        })?;
+
+        let mut model = WaveFrontFile::parse(&contents)
+            .map_err(|e| ObjError { file: Some(filename.to_string()), ..e })?;
 
+        // Material libraries and the textures they mention live next to the .obj file
+        let directory = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+
+        for library in model.material_libraries.clone() {
+            let path = directory.join(&library);
+            let path_name = path.to_string_lossy().into_owned();
+
+            // The geometry is still good without its materials. Faces keep the name-only
+            // materials their usemtl statements created.
+            let Ok(contents) = fs::read_to_string(&path) else {
+                model.missing_material_libraries.push(library);
+                continue;
+            };
+
+            let materials = material::parse(&contents).map_err(|e| ObjError { file: Some(path_name.clone()), ..e })?;
+
+            for mut material in materials {
+                material.resolve_paths(directory);
+                model.add_material(material);
+            }
+        }
+
+        Ok(model)
+    }
+
+    // Parse the contents of a Wavefront .obj file
+    pub fn parse(contents: &str) -> Result<WaveFrontFile, ObjError> {
         let mut vertices: Vec<point::Point3D> = Vec::new();
 
-        let mut faces: Vec<Face> = Vec::new();
+        let mut polygons: Vec<Polygon> = Vec::new();
 
         let mut textures: Vec<point::Point2D> = Vec::new();
-        
-        let vertex_regex = Regex::new(r"(?x)
-                                      v                 # The literal letter v
-                                      \s                # Whitespace
-                                      (?P<x>[0-9-\.e]+) # A floating point number - x
-                                      \s                # Whitespace
-                                      (?P<y>[0-9-\.e]+) # A floating point number - y
-                                      \s                # Whitespace
-                                      (?P<z>[0-9-\.e]+) # A floating point number -z
-                                      ").unwrap();
-
-        let texture_regex = Regex::new(r"(?x)
-                                      vt                # The literal letter vt
-                                      \s\s              # 2 Whitespace
-                                      (?P<u>[0-9-\.e]+) # W floating point number - u
-                                      \s                # Whitespace
-                                      (?P<v>[0-9-\.e]+) # A floating poin tnumber - v
-                                      ").unwrap();
-
-        let face_regex = Regex::new(r"(?x)
-                                    f                                       # The literal letter f
-                                    \s                                      # Whitespace
-                                    (?P<v0>[0-9]*)/(?P<t0>[0-9]*)/[0-9]*    # <number>/<number>/<number
-                                    \s                                      # Whitespace
-                                    (?P<v1>[0-9]*)/(?P<t1>[0-9]*)/[0-9]*    # <number>/<number>/<number>
-                                    \s                                      # Whitespace
-                                    (?P<v2>[0-9]*)/(?P<t2>[0-9]*)/[0-9]*    # <number>/<number>/<number>
-                                    ").unwrap();
-        
-        for line in contents.lines() {
-            if line.starts_with("v ") {
-                let captures = vertex_regex.captures(line).unwrap();
-
-                vertices.push(point::Point3D {
-                    x: f64::from_str(&captures["x"]).unwrap(),
-                    y: f64::from_str(&captures["y"]).unwrap(),
-                    z: f64::from_str(&captures["z"]).unwrap()
-                });
-
-            } else if line.starts_with("f ") {
-                let captures = face_regex.captures(line).unwrap();
-                
-                // The indicies saved in the file are 1-indexed instead of 0 indexed. 
-                // So we substract 1 from each one.
-                faces.push(Face { 
-                    vertices: [
-                        usize::from_str(&captures["v0"]).unwrap() - 1, 
-                        usize::from_str(&captures["v1"]).unwrap() - 1,
-                        usize::from_str(&captures["v2"]).unwrap() - 1],
-                    textures: [
-                        usize::from_str(&captures["t0"]).unwrap() - 1,
-                        usize::from_str(&captures["t1"]).unwrap() - 1,
-                        usize::from_str(&captures["t2"]).unwrap() - 1]
-                });
-            } else if line.starts_with("vt ") {
-                println!("Line: {}", line);
-                let captures = texture_regex.captures(line).unwrap();
-
-                textures.push(point::Point2D {
-                    x: f64::from_str(&captures["u"]).unwrap(),
-                    y: f64::from_str(&captures["v"]).unwrap()
-                });
-            } else {
-                //println!("Have not yet implemented parsing {}", line);
+
+        let mut normals: Vec<vector::Vector3D> = Vec::new();
+
+        let mut materials: Vec<material::Material> = Vec::new();
+
+        let mut material_libraries: Vec<String> = Vec::new();
+
+        // Name from the last usemtl statement, and its index. It only gets a place in `materials`
+        // once a face uses it, so a usemtl that no face follows leaves nothing behind.
+        let mut current_material: Option<(&str, Option<usize>)> = None;
+
+        let mut current_smoothing_group: Option<u32> = None;
+
+        let mut has_smoothing_groups = false;
+
+        // Groups we are in, with the first polygon in them. Their ranges get closed off when the
+        // next o or g statement comes along.
+        let mut current_object: Option<(String, usize)> = None;
+
+        let mut current_groups: Vec<(String, usize)> = Vec::new();
+
+        let mut objects: Vec<Group> = Vec::new();
+
+        let mut groups: Vec<Group> = Vec::new();
+
+        for statement in tokenizer::Tokenizer::new(contents) {
+            let keyword = statement[0];
+            let arguments = &statement[1..];
+
+            match keyword.text {
+                // v x y z [w]. The w is only used by rational curves, which we don't support.
+                "v" => {
+                    let values = parse_floats(&keyword, arguments, 3, 4)?;
+
+                    vertices.push(point::Point3D { x: values[0], y: values[1], z: values[2] });
+                },
+                // vt u [v [w]]. v defaults to 0 and w is only used by 3D textures.
+                "vt" => {
+                    let values = parse_floats(&keyword, arguments, 1, 3)?;
+
+                    textures.push(point::Point2D { x: values[0], y: values.get(1).copied().unwrap_or(0.0) });
+                },
+                "vn" => {
+                    let values = parse_floats(&keyword, arguments, 3, 3)?;
+
+                    normals.push(vector::Vector3D { x: values[0], y: values[1], z: values[2] });
+                },
+                "f" => {
+                    if arguments.len() < 3 {
+                        return Err(ObjError::at(&keyword, ObjErrorKind::TooFewCorners(arguments.len())));
+                    }
+
+                    let mut polygon = parse_polygon(arguments, vertices.len(), textures.len(), normals.len())?;
+                    if let Some((name, index @ None)) = current_material.as_mut() {
+                        *index = Some(materials.iter().position(|m| m.name == *name).unwrap_or_else(|| {
+                            materials.push(material::Material::new(name));
+                            materials.len() - 1
+                        }));
+                    }
+
+                    polygon.material = current_material.and_then(|(_, index)| index);
+                    polygon.smoothing_group = current_smoothing_group;
+
+                    polygons.push(polygon);
+                },
+                "mtllib" => {
+                    material_libraries.extend(arguments.iter().map(|token| token.text.to_string()));
+                },
+                // The material libraries haven't been loaded yet, so all we know about a material
+                // is its name. Its properties get filled in by `new`.
+                "usemtl" => {
+                    if arguments.len() != 1 {
+                        return Err(ObjError::at(&keyword, ObjErrorKind::ArgumentCount { min: 1, max: 1, found: arguments.len() }));
+                    }
+
+                    current_material = Some((arguments[0].text, None));
+                },
+                "o" => {
+                    let name = arguments.iter().map(|token| token.text).collect::<Vec<&str>>().join(" ");
+
+                    close_groups(current_object.take().into_iter(), polygons.len(), &mut objects);
+                    current_object = Some((name, polygons.len()));
+                },
+                // A g without names puts the faces back in the default group
+                "g" => {
+                    close_groups(current_groups.drain(..), polygons.len(), &mut groups);
+
+                    if arguments.is_empty() {
+                        current_groups.push(("default".to_string(), polygons.len()));
+                    }
+
+                    current_groups.extend(arguments.iter().map(|token| (token.text.to_string(), polygons.len())));
+                },
+                "s" => {
+                    if arguments.len() != 1 {
+                        return Err(ObjError::at(&keyword, ObjErrorKind::ArgumentCount { min: 1, max: 1, found: arguments.len() }));
+                    }
+
+                    has_smoothing_groups = true;
+
+                    current_smoothing_group = match arguments[0].text {
+                        "off" => None,
+                        number => match u32::from_str(number) {
+                            Ok(0) => None,
+                            Ok(group) => Some(group),
+                            Err(_) => return Err(ObjError::at(&arguments[0], ObjErrorKind::NotANumber)),
+                        }
+                    };
+                },
+                _ => {
+                    //println!("Have not yet implemented parsing {}", keyword.text);
+                }
             }
         }
 
-        Ok(WaveFrontFile { vertices , faces, textures })
+        close_groups(current_object.into_iter(), polygons.len(), &mut objects);
+        close_groups(current_groups.into_iter(), polygons.len(), &mut groups);
+
+        let faces = triangulate(&vertices, &polygons);
+
+        // Polygons turn into a run of faces, so the face ranges follow from the polygon ones
+        let mut first_face = vec![faces.len(); polygons.len() + 1];
+        for (index, face) in faces.iter().enumerate().rev() {
+            first_face[face.polygon] = index;
+        }
+        for polygon in (0..polygons.len()).rev() {
+            first_face[polygon] = first_face[polygon].min(first_face[polygon + 1]);
+        }
+
+        for group in objects.iter_mut().chain(groups.iter_mut()) {
+            group.faces = first_face[group.polygons.start]..first_face[group.polygons.end];
+        }
+
+        Ok(WaveFrontFile {
+            vertices,
+            polygons,
+            faces,
+            textures,
+            normals,
+            materials,
+            material_libraries,
+            missing_material_libraries: Vec::new(),
+            has_smoothing_groups,
+            objects,
+            groups
+        })
     }
 
     pub fn vertex_count(&self) -> usize {
@@ -114,7 +314,921 @@ impl WaveFrontFile {
         &self.faces[idx]
     }
 
+    pub fn polygon_count(&self) -> usize {
+        self.polygons.len()
+    }
+
+    pub fn get_polygon(&self, idx: usize) -> &Polygon {
+        &self.polygons[idx]
+    }
+
     pub fn get_texture(&self, idx: usize) -> point::Point2D {
         self.textures[idx]
     }
+
+    pub fn normal_count(&self) -> usize {
+        self.normals.len()
+    }
+
+    pub fn get_normal(&self, idx: usize) -> vector::Vector3D {
+        self.normals[idx]
+    }
+
+    pub fn material_count(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn get_material(&self, idx: usize) -> &material::Material {
+        &self.materials[idx]
+    }
+
+    pub fn material_libraries(&self) -> &[String] {
+        &self.material_libraries
+    }
+
+    // The material libraries that couldn't be read when loading the file with `new`, as they
+    // appear in the file. The materials they should have defined only have their names.
+    pub fn missing_material_libraries(&self) -> &[String] {
+        &self.missing_material_libraries
+    }
+
+    // Replace the material with the same name, or add it if no face uses it
+    pub fn add_material(&mut self, material: material::Material) {
+        match self.materials.iter().position(|m| m.name == material.name) {
+            Some(index) => self.materials[index] = material,
+            None => self.materials.push(material),
+        }
+    }
+
+    // Named objects, from o statements
+    pub fn objects(&self) -> &[Group] {
+        &self.objects
+    }
+
+    // Named groups, from g statements
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    // Give every polygon that doesn't have normals some, with the default options: polygons
+    // outside of a smoothing group get a flat normal, polygons in one are smoothed together.
+    // Files without s statements get everything smoothed.
+    pub fn generate_normals(&mut self) {
+        self.generate_normals_with(&NormalOptions::default());
+    }
+
+    // Give every polygon that doesn't have normals some. Each corner gets the weighted average
+    // of the normals of the polygons around its vertex that it is smoothed together with (see
+    // `NormalOptions`). Polygons that already have normals are left alone, and don't take part.
+    pub fn generate_normals_with(&mut self, options: &NormalOptions) {
+        // Newell's normal is twice as long as the polygon's area
+        let newell: Vec<vector::Vector3D> = self.polygons.iter()
+            .map(|polygon| {
+                let points: Vec<point::Point3D> = polygon.vertices.iter().map(|&v| self.vertices[v]).collect();
+
+                triangulation::polygon_normal(&points)
+            })
+            .collect();
+
+        let unit: Vec<vector::Vector3D> = newell.iter().map(|&n| unit_or_zero(n)).collect();
+
+        // The corners around every vertex, as (polygon, corner)
+        let mut around: Vec<Vec<(usize, usize)>> = vec![Vec::new(); self.vertices.len()];
+
+        for (index, polygon) in self.polygons.iter().enumerate().filter(|(_, p)| p.normals.is_none()) {
+            for (corner, &vertex) in polygon.vertices.iter().enumerate() {
+                around[vertex].push((index, corner));
+            }
+        }
+
+        let weight = |polygon: usize, corner: usize| match options.weighting {
+            NormalWeighting::Area => newell[polygon].length() / 2.0,
+            NormalWeighting::Angle => corner_angle(&self.vertices, &self.polygons[polygon].vertices, corner),
+        };
+
+        let use_smoothing_groups = options.use_smoothing_groups && self.has_smoothing_groups;
+
+        let smoothed = |polygon: &Polygon| !use_smoothing_groups || polygon.smoothing_group.is_some();
+
+        // Unit vectors can come out a hair beyond -1 apart, so a crease angle of 180 degrees
+        // needs a little slack to let everything through
+        let min_cosine = options.crease_angle.cos() - 1e-9;
+
+        let mut computed: Vec<Vec<vector::Vector3D>> = Vec::with_capacity(self.polygons.len());
+
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            if polygon.normals.is_some() || !smoothed(polygon) {
+                computed.push(vec![unit[index]; polygon.vertices.len()]);
+                continue;
+            }
+
+            computed.push(polygon.vertices.iter()
+                .map(|&vertex| {
+                    let sum = around[vertex].iter()
+                        .filter(|&&(other, _)| {
+                            let same_group = !use_smoothing_groups
+                                || self.polygons[other].smoothing_group == polygon.smoothing_group;
+
+                            same_group && unit[other].dot(&unit[index]) >= min_cosine
+                        })
+                        .fold(vector::Vector3D::default(), |sum, &(other, corner)| sum + unit[other] * weight(other, corner));
+
+                    // Only degenerate polygons have nothing to average
+                    let normal = unit_or_zero(sum);
+                    if normal == vector::Vector3D::default() { unit[index] } else { normal }
+                })
+                .collect());
+        }
+
+        // Every distinct normal only gets stored once
+        let mut stored: HashMap<[u64; 3], usize> = HashMap::new();
+
+        for (polygon, normals) in self.polygons.iter_mut().zip(computed) {
+            if polygon.normals.is_some() {
+                continue;
+            }
+
+            let all = &mut self.normals;
+
+            polygon.normals = Some(normals.iter()
+                .map(|n| *stored.entry([n.x, n.y, n.z].map(f64::to_bits)).or_insert_with(|| {
+                    all.push(*n);
+                    all.len() - 1
+                }))
+                .collect());
+        }
+
+        self.faces = triangulate(&self.vertices, &self.polygons);
+    }
+
+    // Save the model as a Wavefront .obj file
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(fs::File::create(filename)?);
+
+        self.to_writer(&mut writer)?;
+
+        writer.flush()
+    }
+
+    // Write the model out in the Wavefront .obj format. Parsing the output gives back the same
+    // model. Polygons are written as they are, not as the triangles they were split into.
+    // Material libraries aren't written, only the mtllib statements that refer to them.
+    pub fn to_writer<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        for library in self.material_libraries.iter() {
+            writeln!(writer, "mtllib {}", library)?;
+        }
+
+        // Rust prints the shortest number that parses back to the exact same f64
+        for v in self.vertices.iter() {
+            writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+
+        for vt in self.textures.iter() {
+            writeln!(writer, "vt {} {}", vt.x, vt.y)?;
+        }
+
+        for vn in self.normals.iter() {
+            writeln!(writer, "vn {} {} {}", vn.x, vn.y, vn.z)?;
+        }
+
+        let mut material = None;
+        let mut smoothing_group = None;
+
+        // Nothing below would say there were s statements if they were all `s off`
+        if self.has_smoothing_groups && self.polygons.iter().all(|polygon| polygon.smoothing_group.is_none()) {
+            writeln!(writer, "s off")?;
+        }
+
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            if let Some(object) = self.objects.iter().find(|o| o.polygons.start == index) {
+                writeln!(writer, "o {}", object.name)?;
+            }
+
+            let names: Vec<&str> = self.groups.iter()
+                .filter(|g| g.polygons.start == index)
+                .map(|g| g.name.as_str())
+                .collect();
+
+            if !names.is_empty() {
+                writeln!(writer, "g {}", names.join(" "))?;
+            }
+
+            // There is no way to go back to no material, but parsing never gives us that anyway
+            if polygon.material != material {
+                if let Some(m) = polygon.material {
+                    writeln!(writer, "usemtl {}", self.materials[m].name)?;
+                }
+
+                material = polygon.material;
+            }
+
+            if polygon.smoothing_group != smoothing_group {
+                match polygon.smoothing_group {
+                    Some(group) => writeln!(writer, "s {}", group)?,
+                    None => writeln!(writer, "s off")?,
+                }
+
+                smoothing_group = polygon.smoothing_group;
+            }
+
+            write!(writer, "f")?;
+
+            for corner in 0..polygon.vertices.len() {
+                // Back to 1-indexed. The texture index goes in the middle even when it's empty.
+                write!(writer, " {}", polygon.vertices[corner] + 1)?;
+
+                match (&polygon.textures, &polygon.normals) {
+                    (None, None) => {},
+                    (Some(t), None) => write!(writer, "/{}", t[corner] + 1)?,
+                    (None, Some(n)) => write!(writer, "//{}", n[corner] + 1)?,
+                    (Some(t), Some(n)) => write!(writer, "/{}/{}", t[corner] + 1, n[corner] + 1)?,
+                }
+            }
+
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+
+    // Look up the position, texture coordinate and normal of corner `corner` (0, 1 or 2) of face
+    // `face`
+    pub fn get_corner(&self, face: usize, corner: usize) -> Corner {
+        let face = &self.faces[face];
+
+        Corner {
+            position: self.vertices[face.vertices[corner]],
+            texture: face.textures.map(|textures| self.textures[textures[corner]]),
+            normal: face.normals.map(|normals| self.normals[normals[corner]])
+        }
+    }
+}
+
+// Parse the arguments of a `keyword` statement that takes between `min` and `max` numbers
+pub(crate) fn parse_floats(keyword: &tokenizer::Token, arguments: &[tokenizer::Token], min: usize, max: usize) -> Result<Vec<f64>, ObjError> {
+    if arguments.len() < min || arguments.len() > max {
+        return Err(ObjError::at(keyword, ObjErrorKind::ArgumentCount { min, max, found: arguments.len() }));
+    }
+
+    arguments.iter()
+        .map(|token| f64::from_str(token.text).map_err(|_| ObjError::at(token, ObjErrorKind::NotANumber)))
+        .collect()
+}
+
+// A corner is v, v/t, v//n or v/t/n
+fn parse_polygon(corners: &[tokenizer::Token], vertex_count: usize, texture_count: usize, normal_count: usize) -> Result<Polygon, ObjError> {
+    let mut vertices = Vec::with_capacity(corners.len());
+    let mut textures = Vec::with_capacity(corners.len());
+    let mut normals = Vec::with_capacity(corners.len());
+
+    for corner in corners {
+        let parts: Vec<&str> = corner.text.split('/').collect();
+
+        if parts.len() > 3 || parts[0].is_empty() {
+            return Err(ObjError::at(corner, ObjErrorKind::InvalidCorner));
+        }
+
+        vertices.push(resolve_index(corner, parts[0], vertex_count)?);
+
+        // An empty index (the `t` in `v//n`) means the corner doesn't have one
+        if let Some(t) = parts.get(1).filter(|t| !t.is_empty()) {
+            textures.push(resolve_index(corner, t, texture_count)?);
+        }
+
+        if let Some(n) = parts.get(2).filter(|n| !n.is_empty()) {
+            normals.push(resolve_index(corner, n, normal_count)?);
+        }
+    }
+
+    let complete = |indices: Vec<usize>| {
+        if indices.len() == vertices.len() { Some(indices) } else { None }
+    };
+
+    Ok(Polygon { textures: complete(textures), normals: complete(normals), vertices, material: None, smoothing_group: None })
+}
+
+// Turn an index from a face corner into an index into a list that currently has `count` elements.
+// The indices saved in the file are 1-indexed instead of 0 indexed, so we subtract 1. Negative
+// indices count backwards from the end of the list: -1 is the last element read so far.
+fn resolve_index(corner: &tokenizer::Token, index: &str, count: usize) -> Result<usize, ObjError> {
+    let index = i64::from_str(index).map_err(|_| ObjError::at(corner, ObjErrorKind::InvalidCorner))?;
+
+    let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(ObjError::at(corner, ObjErrorKind::IndexOutOfRange { index, count }));
+    }
+
+    Ok(resolved as usize)
+}
+
+// Why a Wavefront file couldn't be loaded, and where in the file the problem is
+#[derive(Debug)]
+pub struct ObjError {
+    // The file being loaded. `None` when parsing a string.
+    pub file: Option<String>,
+    // 1-indexed line and column of the offending token. Both are 0 if the file couldn't be read.
+    pub line: usize,
+    pub column: usize,
+    // The offending token, exactly as it appears in the file
+    pub token: String,
+    pub kind: ObjErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ObjErrorKind {
+    // The file couldn't be read
+    Io(io::Error),
+    // A statement got the wrong number of arguments. The token is the statement's keyword.
+    ArgumentCount { min: usize, max: usize, found: usize },
+    NotANumber,
+    // A face with fewer than 3 corners. The token is the `f`.
+    TooFewCorners(usize),
+    // A face corner that isn't `v`, `v/t`, `v//n` or `v/t/n`
+    InvalidCorner,
+    // A face corner refers to a vertex, texture coordinate or normal that doesn't exist (yet)
+    IndexOutOfRange { index: i64, count: usize },
+    // A material library statement that comes before any `newmtl`
+    NoMaterial,
+}
+
+impl ObjError {
+    pub(crate) fn at(token: &tokenizer::Token, kind: ObjErrorKind) -> ObjError {
+        ObjError { file: None, line: token.line, column: token.column, token: token.text.to_string(), kind }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = self.file.as_deref().unwrap_or("<string>");
+        let at = format!("{}:{}:{}", file, self.line, self.column);
+
+        match &self.kind {
+            ObjErrorKind::Io(e) => write!(f, "{}: {}", file, e),
+            ObjErrorKind::ArgumentCount { min, max, found } =>
+                write!(f, "{}: '{}' takes {} to {} arguments, got {}", at, self.token, min, max, found),
+            ObjErrorKind::NotANumber => write!(f, "{}: '{}' is not a number", at, self.token),
+            ObjErrorKind::TooFewCorners(found) => write!(f, "{}: a face needs at least 3 corners, got {}", at, found),
+            ObjErrorKind::InvalidCorner => write!(f, "{}: invalid face corner '{}'", at, self.token),
+            ObjErrorKind::IndexOutOfRange { index, count } =>
+                write!(f, "{}: index {} in '{}' is out of range, there are {} elements", at, index, self.token, count),
+            ObjErrorKind::NoMaterial => write!(f, "{}: '{}' comes before any 'newmtl'", at, self.token),
+        }
+    }
+}
+
+impl error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            ObjErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+fn unit_or_zero(v: vector::Vector3D) -> vector::Vector3D {
+    if v.length() > 0.0 { v.normalized() } else { v }
+}
+
+// The angle between the two edges of a polygon that meet at `corner`
+fn corner_angle(vertices: &[point::Point3D], polygon: &[usize], corner: usize) -> f64 {
+    let n = polygon.len();
+    let at = vertices[polygon[corner]];
+
+    let a = vertices[polygon[(corner + n - 1) % n]] - at;
+    let b = vertices[polygon[(corner + 1) % n]] - at;
+
+    if a.length() == 0.0 || b.length() == 0.0 {
+        return 0.0;
+    }
+
+    (a.dot(&b) / (a.length() * b.length())).clamp(-1.0, 1.0).acos()
+}
+
+// Record the polygon ranges of the groups that end at polygon `end`. Groups without any
+// polygons are dropped.
+fn close_groups(open: impl Iterator<Item = (String, usize)>, end: usize, closed: &mut Vec<Group>) {
+    for (name, start) in open {
+        if start < end {
+            closed.push(Group { name, polygons: start..end, faces: 0..0 });
+        }
+    }
+}
+
+// Split every polygon into triangles
+fn triangulate(vertices: &[point::Point3D], polygons: &[Polygon]) -> Vec<Face> {
+    let mut faces = Vec::with_capacity(polygons.len());
+
+    for (index, polygon) in polygons.iter().enumerate() {
+        let points: Vec<point::Point3D> = polygon.vertices.iter().map(|&v| vertices[v]).collect();
+
+        for [a, b, c] in triangulation::triangulate(&points) {
+            faces.push(Face {
+                vertices: [polygon.vertices[a], polygon.vertices[b], polygon.vertices[c]],
+                textures: polygon.textures.as_ref().map(|t| [t[a], t[b], t[c]]),
+                normals: polygon.normals.as_ref().map(|n| [n[a], n[b], n[c]]),
+                material: polygon.material,
+                polygon: index
+            });
+        }
+    }
+
+    faces
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::material::Material;
+    use crate::point::{Point2D, Point3D};
+    use crate::test_util;
+    use crate::vector::Vector3D;
+    use crate::wavefront::{Group, NormalOptions, NormalWeighting, ObjErrorKind, WaveFrontFile};
+
+    #[test]
+    fn test_quads_and_ngons_are_triangulated() {
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0.5 2 0
+vt  0 0 0
+vn  0 0 1
+f 1/1/1 2/1/1 3/1/1 4/1/1
+f 4/1/1 3/1/1 5/1/1
+f 1/1/1 2/1/1 3/1/1 5/1/1 4/1/1
+";
+        let model = WaveFrontFile::parse(obj).unwrap();
+
+        assert_eq!(model.polygon_count(), 3);
+        assert_eq!(model.face_count(), 2 + 1 + 3);
+
+        let polygons: Vec<usize> = (0..model.face_count()).map(|i| model.get_face(i).polygon).collect();
+        assert_eq!(polygons, vec![0, 0, 1, 2, 2, 2]);
+
+        assert_eq!(model.get_face(1).vertices, [0, 2, 3]);
+        assert_eq!(model.get_polygon(2).vertices, vec![0, 1, 2, 4, 3]);
+    }
+
+    #[test]
+    fn test_corner_forms() {
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+vt  0.5 0.5
+vn  0 0 1
+f 1 2 3
+f 1/1 2/1 3/1
+f 1//1 2//1 3//1
+f 1/1/1 2/1/1 3/1/1
+f 1/1 2//1 3/1/1
+";
+        let model = WaveFrontFile::parse(obj).unwrap();
+
+        let has = |face: usize| (model.get_face(face).textures.is_some(), model.get_face(face).normals.is_some());
+        assert_eq!(has(0), (false, false));
+        assert_eq!(has(1), (true, false));
+        assert_eq!(has(2), (false, true));
+        assert_eq!(has(3), (true, true));
+        // Mixed forms: only the vertices are complete
+        assert_eq!(has(4), (false, false));
+
+        let corner = model.get_corner(0, 1);
+        assert!(corner.texture.is_none() && corner.normal.is_none());
+        assert_eq!(model.get_corner(3, 2).normal, Some(Vector3D::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_normals_come_back_through_corners() {
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+vn 0.5 -0.25 2
+vn -1 0 0
+f 1/1/3 2/2/1 3/3/2
+f 3//2 2//3 1//1
+";
+        let model = WaveFrontFile::parse(obj).unwrap();
+        assert_eq!(model.normal_count(), 3);
+
+        let normal = |face: usize, corner: usize| model.get_corner(face, corner).normal.unwrap();
+
+        // The normal index is the third one, whatever the texture index is
+        assert_eq!(normal(0, 0), Vector3D::new(-1.0, 0.0, 0.0));
+        assert_eq!(normal(0, 1), Vector3D::new(0.0, 0.0, 1.0));
+        assert_eq!(normal(0, 2), Vector3D::new(0.5, -0.25, 2.0));
+        assert_eq!(model.get_corner(0, 2).texture, Some(Point2D { x: 1.0, y: 1.0 }));
+
+        assert_eq!(normal(1, 0), Vector3D::new(0.5, -0.25, 2.0));
+        assert_eq!(normal(1, 1), Vector3D::new(-1.0, 0.0, 0.0));
+        assert_eq!(normal(1, 2), Vector3D::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_negative_indices() {
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+vt  0 0
+f -3/-1 -2/-1 -1/-1
+v 0 1 0
+f -4 -2 -1
+";
+        let model = WaveFrontFile::parse(obj).unwrap();
+
+        assert_eq!(model.get_face(0).vertices, [0, 1, 2]);
+        assert_eq!(model.get_face(0).textures, Some([0, 0, 0]));
+        // Relative to the vertices read so far, not the whole file
+        assert_eq!(model.get_face(1).vertices, [0, 2, 3]);
+    }
+
+    #[test]
+    fn test_bad_indices_are_errors() {
+        assert!(WaveFrontFile::parse("v 0 0 0\nf 1 1 2\n").is_err());
+        assert!(WaveFrontFile::parse("v 0 0 0\nf 0 1 1\n").is_err());
+        assert!(WaveFrontFile::parse("v 0 0 0\nf -2 1 1\n").is_err());
+        assert!(WaveFrontFile::parse("v 0 0 0\nf 1/1 1 1\n").is_err());
+    }
+
+    // The same triangle, written in all the ways the spec allows
+    const EQUIVALENT_FILES: &[&str] = &[
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\n",
+        "v\t0\t0\t0\nv  1   0 0\nv 0 1 0\nvt\t\t0 0\nvn 0 0 1\nf 1/1/1\t2/1/1  3/1/1",
+        "v 0 0 0\r\nv 1 0 0\r\nv 0 1 0\r\nvt 0 0\r\nvn 0 0 1\r\nf 1/1/1 2/1/1 3/1/1\r\n",
+        "# A triangle\nv 0 0 0 # first\nv 1 0 0\n\n   \nv 0 1 0#no space\nvt 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1 # done",
+        "v 0 0 0 1\nv 1.0e+0 0 0 1.0\nv 0 1 0\nvt 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\n",
+        "v 0.0 0E0 -0\nv 1e0 0 0\nv 0 10e-1 +0\nvt 0 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 \\\n 3/1/1\n",
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf -3/-1/-1 -2/-1/-1 -1/-1/-1\n",
+        "v 0 0 0\nv 1 0 \\\n0\nv 0 1 0\nvt 0 0\nvn 0 0 1\no triangle\ng group\ns 1\nusemtl none\nf 1/1/1 2/1/1 3/1/1\n",
+    ];
+
+    const MALFORMED_FILES: &[&str] = &[
+        "v 0 0\n",
+        "v 0 0 0 1 2\n",
+        "v 0 zero 0\n",
+        "vn 0 0\n",
+        "vt\n",
+        "v 0 0 0\nf 1 1\n",
+        "v 0 0 0\nf 1/1/1/1 1 1\n",
+        "v 0 0 0\nf /1 1 1\n",
+        "v 0 0 0\nf 1 1 one\n",
+        "v 0 0 0\nf 1 1 99999999999999999999\n",
+    ];
+
+    #[test]
+    fn test_equivalent_spellings() {
+        for obj in EQUIVALENT_FILES {
+            let model = WaveFrontFile::parse(obj).unwrap_or_else(|e| panic!("{:?}: {}", obj, e));
+
+            assert_eq!(model.vertex_count(), 3, "{:?}", obj);
+            assert_eq!(model.face_count(), 1, "{:?}", obj);
+            assert_eq!(model.get_vertex(1), Point3D { x: 1.0, y: 0.0, z: 0.0 }, "{:?}", obj);
+            assert_eq!(model.get_vertex(2), Point3D { x: 0.0, y: 1.0, z: 0.0 }, "{:?}", obj);
+            assert_eq!(model.get_texture(0), Point2D { x: 0.0, y: 0.0 }, "{:?}", obj);
+
+            let face = model.get_face(0);
+            assert_eq!(face.vertices, [0, 1, 2], "{:?}", obj);
+            assert_eq!(face.textures, Some([0, 0, 0]), "{:?}", obj);
+            assert_eq!(face.normals, Some([0, 0, 0]), "{:?}", obj);
+        }
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        for obj in MALFORMED_FILES {
+            assert!(WaveFrontFile::parse(obj).is_err(), "{:?}", obj);
+        }
+    }
+
+    #[test]
+    fn test_error_location() {
+        let error = WaveFrontFile::parse("v 0 0 0\n# comment\nv 1 0 0\nf 1 2  \t2/x/1\n").err().unwrap();
+
+        assert_eq!((error.line, error.column, error.token.as_str()), (4, 9, "2/x/1"));
+        assert!(matches!(error.kind, ObjErrorKind::InvalidCorner));
+        assert_eq!(error.to_string(), "<string>:4:9: invalid face corner '2/x/1'");
+
+        let error = WaveFrontFile::parse("v 0 0 0\nf 1 1 \\\n   -2\n").err().unwrap();
+
+        assert_eq!((error.line, error.column, error.token.as_str()), (3, 4, "-2"));
+        assert!(matches!(error.kind, ObjErrorKind::IndexOutOfRange { index: -2, count: 1 }));
+
+        let error = WaveFrontFile::parse("vt 0.5 1e+ 0").err().unwrap();
+
+        assert_eq!((error.line, error.column, error.token.as_str()), (1, 8, "1e+"));
+        assert!(matches!(error.kind, ObjErrorKind::NotANumber));
+    }
+
+    #[test]
+    fn test_missing_file_is_an_error() {
+        let error = WaveFrontFile::new("obj/does_not_exist.obj").err().unwrap();
+
+        assert_eq!(error.file.as_deref(), Some("obj/does_not_exist.obj"));
+        assert!(matches!(error.kind, ObjErrorKind::Io(_)));
+    }
+
+    #[test]
+    fn test_usemtl_assigns_materials_to_faces() {
+        let obj = "mtllib a.mtl b.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 1 1 0
+f 1 2 3
+usemtl red
+f 1 2 3
+usemtl blue
+# A quad gets split, both halves share the material
+f 1 2 \\
+  4 3
+usemtl red
+f 1 2 3
+";
+        let model = WaveFrontFile::parse(obj).unwrap();
+
+        assert_eq!(model.material_libraries(), &["a.mtl".to_string(), "b.mtl".to_string()]);
+        assert_eq!(model.material_count(), 2);
+        assert_eq!(model.get_material(0).name, "red");
+        assert_eq!(model.get_material(1).name, "blue");
+
+        let materials: Vec<Option<usize>> = (0..model.face_count()).map(|i| model.get_face(i).material).collect();
+        assert_eq!(materials, vec![None, Some(0), Some(1), Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn test_material_libraries_are_loaded_next_to_the_obj() {
+        let directory = std::env::temp_dir().join(format!("renderer_mtl_test_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        std::fs::write(directory.join("model.obj"), "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl skin\nf 1 2 3\n").unwrap();
+        std::fs::write(directory.join("model.mtl"), "newmtl unused\nnewmtl skin\nKd 1 0 0\nmap_Kd textures/skin.tga\n").unwrap();
+
+        let model = WaveFrontFile::new(directory.join("model.obj").to_str().unwrap()).unwrap();
+
+        let skin = model.get_material(model.get_face(0).material.unwrap());
+        assert_eq!(skin.diffuse, Vector3D::new(1.0, 0.0, 0.0));
+        assert_eq!(skin.diffuse_map, Some(directory.join("textures/skin.tga")));
+        assert_eq!(model.material_count(), 2);
+
+        std::fs::write(directory.join("model.mtl"), "newmtl skin\nKd red\n").unwrap();
+        let error = WaveFrontFile::new(directory.join("model.obj").to_str().unwrap()).err().unwrap();
+        assert_eq!(error.file, Some(directory.join("model.mtl").to_string_lossy().into_owned()));
+        assert_eq!((error.line, error.column), (2, 4));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_missing_material_library() {
+        let directory = std::env::temp_dir().join(format!("renderer_missing_mtl_test_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        std::fs::write(directory.join("model.obj"), "mtllib here.mtl gone.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl skin\nf 1 2 3\nusemtl metal\nf 1 3 2\n").unwrap();
+        std::fs::write(directory.join("here.mtl"), "newmtl metal\nKs 1 1 1\n").unwrap();
+
+        let model = WaveFrontFile::new(directory.join("model.obj").to_str().unwrap()).unwrap();
+
+        assert_eq!(model.face_count(), 2);
+        assert_eq!(model.missing_material_libraries(), ["gone.mtl"]);
+
+        // The material from the missing library only has its name, the other one got loaded
+        assert_eq!(model.get_material(model.get_face(0).material.unwrap()), &Material::new("skin"));
+        assert_eq!(model.get_material(model.get_face(1).material.unwrap()).specular, Vector3D::new(1.0, 1.0, 1.0));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_objects_and_groups() {
+        let obj = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+g
+o body
+g torso arms
+f 1 2 3 4
+f 1 2 3
+o head
+g
+g head
+f 1 2 3
+g torso
+f 1 2 3 4
+";
+        let model = WaveFrontFile::parse(obj).unwrap();
+
+        let ranges = |groups: &[Group]| groups.iter()
+            .map(|g| (g.name.clone(), g.polygons.clone(), g.faces.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranges(model.objects()), vec![
+            ("body".to_string(), 0..2, 0..3),
+            ("head".to_string(), 2..4, 3..6),
+        ]);
+
+        assert_eq!(ranges(model.groups()), vec![
+            ("torso".to_string(), 0..2, 0..3),
+            ("arms".to_string(), 0..2, 0..3),
+            ("head".to_string(), 2..3, 3..4),
+            ("torso".to_string(), 3..4, 4..6),
+        ]);
+    }
+
+    #[test]
+    fn test_smoothing_groups() {
+        // Two squares folded along the y axis, one lying flat and one standing up
+        let obj = "v 0 0 0
+v 0 1 0
+v -1 1 0
+v -1 0 0
+v 0 0 1
+v 0 1 1
+s 1
+f 1 2 3 4
+s off
+f 1 2 6 5
+s 1
+f 1 2 6 5
+s 2
+f 1 2 6 5
+";
+        let mut model = WaveFrontFile::parse(obj).unwrap();
+
+        let groups: Vec<Option<u32>> = (0..4).map(|i| model.get_polygon(i).smoothing_group).collect();
+        assert_eq!(groups, vec![Some(1), None, Some(1), Some(2)]);
+
+        assert!(WaveFrontFile::parse("s on\n").is_err());
+
+        model.generate_normals();
+
+        let normal = |face: usize, corner: usize| model.get_corner(face, corner).normal.unwrap();
+        let close = |a: Vector3D, b: Vector3D| (a - b).length() < 1e-9;
+
+        let flat = Vector3D::new(0.0, 0.0, 1.0);
+        let standing = Vector3D::new(1.0, 0.0, 0.0);
+        let shared = Vector3D::new(1.0, 0.0, 1.0).normalized();
+
+        // The polygon vertex 1 (index 0) is the fold. Polygon 0 is faces 0 and 1.
+        assert!(close(normal(0, 0), shared));
+        // Vertex 3 (index 2) isn't on the fold
+        assert!(close(normal(0, 2), flat));
+        // No smoothing group: flat
+        assert!((0..3).all(|c| close(normal(2, c), standing)));
+        // Smoothing group 1 shares the fold with polygon 0
+        assert!(close(normal(4, 0), shared));
+        assert!(close(normal(4, 2), standing));
+        // Smoothing group 2 has nobody to share with
+        assert!(close(normal(6, 0), standing));
+    }
+
+    fn round_trip(model: &WaveFrontFile) -> WaveFrontFile {
+        let mut output = Vec::new();
+        model.to_writer(&mut output).unwrap();
+
+        WaveFrontFile::parse(std::str::from_utf8(&output).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let obj = "mtllib a.mtl
+v 0.1 0 0
+v 1 0 1e-300
+v 1 1 -0
+v 0 1 0
+v 0.3333333333333333 0.5 0.25
+vt 0.5 0.25
+vt 1 1
+vn 0 0 1
+o thing
+g a b
+usemtl red
+f 1 2 3 4
+s 3
+f 1/1 2/2 -2/1
+usemtl blue
+g
+f 1//1 2//1 3//1
+s off
+o other
+f -5/1/1 -4/2/1 -3/2/1 -1/1/1
+";
+        let mut model = WaveFrontFile::parse(obj).unwrap();
+
+        assert_eq!(round_trip(&model), model);
+
+        // Normals we made up ourselves survive too
+        model.generate_normals();
+        assert_eq!(round_trip(&model), model);
+
+        // A material no face uses doesn't get written, so it mustn't get a place either
+        let model = WaveFrontFile::parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl a\nusemtl b\nf 1 2 3\nusemtl c\n").unwrap();
+        assert_eq!(model.get_polygon(0).material, Some(0));
+        assert_eq!(model.get_material(0).name, "b");
+        assert_eq!(round_trip(&model), model);
+
+        // Saying `s off` still means the file has smoothing groups, if empty ones
+        let model = WaveFrontFile::parse("v 0 0 0\nv 1 0 0\nv 0 1 0\ns off\nf 1 2 3\n").unwrap();
+        assert_eq!(round_trip(&model), model);
+    }
+
+    #[test]
+    fn test_round_trip_head() {
+        let model = WaveFrontFile::parse(&std::fs::read_to_string("obj/head.obj").unwrap()).unwrap();
+
+        assert_eq!(round_trip(&model), model);
+    }
+
+    #[test]
+    fn test_normal_weighting_and_crease_angle() {
+        // A unit square lying flat, and a thin strip standing up along its edge at x = 0
+        let obj = "v 0 0 0
+v 0 1 0
+v -1 1 0
+v -1 0 0
+v 0 0 0.1
+v 0 1 0.1
+f 1 2 3 4
+f 1 2 6 5
+";
+        let generate = |options: NormalOptions| {
+            let mut model = WaveFrontFile::parse(obj).unwrap();
+            model.generate_normals_with(&options);
+
+            // Vertex 1 on the flat square, and the corner of the strip away from the fold
+            (model.get_corner(0, 0).normal.unwrap(), model.get_corner(2, 2).normal.unwrap())
+        };
+
+        let close = |a: Vector3D, b: Vector3D| (a - b).length() < 1e-9;
+        let standing = Vector3D::new(1.0, 0.0, 0.0);
+
+        let everything = NormalOptions { use_smoothing_groups: false, ..NormalOptions::default() };
+
+        // Both polygons have a right angle at the fold
+        let (fold, corner) = generate(NormalOptions { weighting: NormalWeighting::Angle, ..everything });
+        assert!(close(fold, Vector3D::new(1.0, 0.0, 1.0).normalized()));
+        assert!(close(corner, standing));
+
+        // The square is ten times bigger than the strip
+        let (fold, _) = generate(NormalOptions { weighting: NormalWeighting::Area, ..everything });
+        assert!(close(fold, Vector3D::new(0.1, 0.0, 1.0).normalized()));
+
+        // A right angle is sharper than the crease angle
+        let (fold, _) = generate(NormalOptions { crease_angle: 80.0_f64.to_radians(), ..everything });
+        assert!(close(fold, Vector3D::new(0.0, 0.0, 1.0)));
+
+        // No smoothing groups in the file, so the defaults smooth everything
+        let (fold, _) = generate(NormalOptions::default());
+        assert!(close(fold, Vector3D::new(1.0, 0.0, 1.0).normalized()));
+
+        // With s statements in it the groups count, and being in none of them means flat
+        let mut model = WaveFrontFile::parse(&format!("s off\n{}", obj)).unwrap();
+        model.generate_normals();
+        assert!(close(model.get_corner(0, 0).normal.unwrap(), Vector3D::new(0.0, 0.0, 1.0)));
+    }
+
+    // Throw random edits of the files above at the parser. Whatever comes out, it mustn't panic.
+    #[test]
+    fn test_random_mutations_do_not_panic() {
+        const PIECES: &[&str] = &[
+            "v", "vt", "vn", "f", " ", "\t", "\n", "\r\n", "/", "//", "-", "+", ".", "e", "0", "1", "7",
+            "#", "\\", "\\\n", "nan", "inf", "\u{e9}", "\u{3000}",
+        ];
+
+        // The same every time
+        let mut random = {
+            let mut random = test_util::random(0xda94_2042_e4dd_58b5);
+            move |bound: usize| (random() * bound as f64) as usize
+        };
+
+        let seeds: Vec<&str> = EQUIVALENT_FILES.iter().chain(MALFORMED_FILES.iter()).copied().collect();
+
+        for _ in 0..5000 {
+            let mut obj: String = seeds[random(seeds.len())].to_string();
+
+            for _ in 0..1 + random(4) {
+                let mut at = random(obj.len() + 1);
+                while !obj.is_char_boundary(at) {
+                    at -= 1;
+                }
+
+                match random(3) {
+                    0 => obj.insert_str(at, PIECES[random(PIECES.len())]),
+                    1 => { obj.truncate(at); },
+                    _ => {
+                        let mut end = (at + 1 + random(3)).min(obj.len());
+                        while !obj.is_char_boundary(end) {
+                            end += 1;
+                        }
+                        obj.replace_range(at..end, "");
+                    }
+                }
+            }
+
+            let _ = WaveFrontFile::parse(&obj);
+        }
+    }
 }